@@ -94,6 +94,43 @@ impl Debug for Rope {
 }
 */
 
+// For each prefix of `needle`, the length of its longest proper prefix that
+// is also a suffix.
+fn kmp_table(needle: &[u8]) -> Vec<usize> {
+    let mut table = vec![0; needle.len()];
+    let mut k = 0;
+    for i in 1..needle.len() {
+        while k > 0 && needle[i] != needle[k] {
+            k = table[k - 1];
+        }
+        if needle[i] == needle[k] {
+            k += 1;
+        }
+        table[i] = k;
+    }
+    table
+}
+
+// The offset of the first occurrence of `needle` in `haystack`, counting
+// from `from`, using the table made by `kmp_table(needle)`.
+fn kmp_find<I: Iterator<Item = u8>>(needle: &[u8], table: &[usize], haystack: I, from: usize) -> Option<usize> {
+    let mut matched = 0;
+    let mut offset = from;
+    for b in haystack {
+        while matched > 0 && needle[matched] != b {
+            matched = table[matched - 1];
+        }
+        if needle[matched] == b {
+            matched += 1;
+        }
+        offset += 1;
+        if matched == needle.len() {
+            return Some(offset - needle.len());
+        }
+    }
+    None
+}
+
 // TODO: explore ways to make this faster - SIMD would be a big win
 fn count_newlines(s: &str) -> usize {
     s.as_bytes().iter().filter(|&&c| c == b'\n').count()
@@ -296,6 +333,26 @@ impl Rope {
         self.root.byte_at(offset + self.start)
     }
 
+    /// Return the byte offset of the first occurrence of `needle` starting at
+    /// or after `from`, or `None` if there is none.
+    ///
+    /// The search streams over the chunks of the rope, so matches that span
+    /// chunk boundaries are found without copying.
+    ///
+    /// Time complexity: O(n + m) (Knuth-Morris-Pratt)
+    pub fn find(&self, needle: &str, from: usize) -> Option<usize> {
+        if from > self.len {
+            return None;
+        }
+        let needle = needle.as_bytes();
+        if needle.is_empty() {
+            return Some(from);
+        }
+        let rest = self.clone().slice(from, self.len);
+        let bytes = rest.iter_chunks().flat_map(|chunk| chunk.bytes());
+        kmp_find(needle, &kmp_table(needle), bytes, from)
+    }
+
     // return condition: result is_full
     // TODO: maybe return a node, we always seem to use that?
     fn normalize(self) -> Rope {
@@ -1129,6 +1186,36 @@ fn replace_rope_small() {
     assert_eq!("herald", String::from(a));
 }
 
+#[test]
+fn find_small() {
+    let a = Rope::from("abaababaab");
+    assert_eq!(Some(3), a.find("abab", 0));
+    assert_eq!(Some(5), a.find("aba", 4));
+    assert_eq!(None, a.find("abb", 0));
+    assert_eq!(Some(7), a.find("", 7));
+    assert_eq!(None, a.find("a", 11));
+    let b = a.slice(2, 9);
+    assert_eq!(Some(1), b.find("abab", 0));
+    assert_eq!(None, b.find("baab", 4));
+}
+
+#[test]
+fn find_across_chunks() {
+    let mut s = String::new();
+    for _ in 0..3 * MAX_LEAF {
+        s.push('a');
+    }
+    s.push('b');
+    for _ in 0..MAX_LEAF {
+        s.push('a');
+    }
+    let a = Rope::from(&s);
+    assert!(a.iter_chunks().count() > 1);
+    let needle = "a".repeat(MAX_LEAF) + "b";
+    assert_eq!(s.find(&needle), a.find(&needle, 0));
+    assert_eq!(Some(3 * MAX_LEAF + 1), a.find(&"a".repeat(MAX_LEAF), 2 * MAX_LEAF + 1));
+}
+
 #[test]
 fn line_of_offset_small() {
     let a = Rope::from("a\nb\nc");
@@ -17,9 +17,11 @@
 
 use std::cmp::{min,max};
 use std::borrow::Cow;
+use std::mem;
 
-use tree::{Leaf, Node, NodeInfo, Metric, TreeBuilder, Cursor};
+use tree::{Leaf, Node, NodeInfo, Metric, TreeBuilder, Cursor, ByteLeaf, LeafCursor};
 use interval::Interval;
+use {kmp_table, kmp_find};
 
 const MIN_LEAF: usize = 511;
 const MAX_LEAF: usize = 1024;
@@ -331,6 +333,43 @@ impl Rope {
         leaf.as_bytes()[pos]
     }
 
+    /// Returns the byte range [`start`..`end`) as a new rope, sharing
+    /// storage with `self`.
+    ///
+    /// Time complexity: O(log n)
+    pub fn slice(&self, start: usize, end: usize) -> Rope {
+        self.subseq(Interval::new_closed_open(start, end))
+    }
+
+    /// Append `s` to the string.
+    pub fn push_str(&mut self, s: &str) {
+        let len = self.len();
+        self.edit_str(len, len, s);
+    }
+
+    /// Append rope `r` to the string.
+    ///
+    /// Time complexity: O(log n)
+    pub fn push(&mut self, r: Rope) {
+        let rope = mem::take(self);
+        *self = Node::concat(rope, r);
+    }
+
+    /// Return the byte offset of the first occurrence of `needle` starting at
+    /// or after `from`, or `None` if there is none.
+    ///
+    /// Time complexity: O(n + m) (Knuth-Morris-Pratt)
+    pub fn find(&self, needle: &str, from: usize) -> Option<usize> {
+        if from > self.len() {
+            return None;
+        }
+        let needle = needle.as_bytes();
+        if needle.is_empty() {
+            return Some(from);
+        }
+        kmp_find(needle, &kmp_table(needle), ByteCursor::new(self, from), from)
+    }
+
     // TODO: this should be a Cow
     // TODO: a case can be made to hang this on Cursor instead
     pub fn slice_to_string(&self, start: usize, end: usize) -> String {
@@ -414,6 +453,23 @@ impl<'a> Cursor<'a, RopeInfo> {
     }
 }
 
+impl ByteLeaf for String {
+    fn byte_at(&self, offset: usize) -> u8 {
+        self.as_bytes()[offset]
+    }
+}
+
+/// A cursor over the bytes of a rope.
+pub type ByteCursor<'a> = LeafCursor<'a, RopeInfo>;
+
+impl PartialEq for Rope {
+    fn eq(&self, other: &Rope) -> bool {
+        self.len() == other.len() && ByteCursor::new(self, 0).eq(ByteCursor::new(other, 0))
+    }
+}
+
+impl Eq for Rope {}
+
 // line iterators
 
 pub struct LinesRaw<'a> {
@@ -494,7 +550,7 @@ impl<'a> Iterator for Lines<'a> {
 
 #[cfg(test)]
 mod tests {
-    use rope::Rope;
+    use rope::{Rope, ByteCursor};
 
     #[test]
     fn replace_small() {
@@ -503,6 +559,69 @@ mod tests {
         assert_eq!("herald", String::from(a));
     }
 
+    #[test]
+    fn slice_push_small() {
+        let a = Rope::from("hello world");
+        let mut b = a.slice(1, 9);
+        assert_eq!("ello wor", String::from(&b));
+        b.push(a.slice(0, 5));
+        b.push_str("!");
+        assert_eq!("ello worhello!", String::from(&b));
+        assert!(b == Rope::from("ello worhello!"));
+        assert!(a != b);
+    }
+
+    fn long_rope() -> (String, Rope) {
+        let s = (0..5000).map(|i| (b'a' + (i * 7 % 26) as u8) as char).collect::<String>();
+        let mut r = Rope::from(&s[..1500]);
+        r.push(Rope::from(&s[1500..]));
+        (s, r)
+    }
+
+    #[test]
+    fn byte_cursor_small() {
+        let a = Rope::from("abc");
+        let mut c = ByteCursor::new(&a, 1);
+        assert_eq!(Some(b'b'), c.peek());
+        assert_eq!(Some(b'b'), c.next());
+        assert_eq!(2, c.pos());
+        assert_eq!(Some(b'c'), c.next());
+        assert_eq!(None, c.next());
+        assert_eq!(3, c.pos());
+        assert_eq!(Some(b'c'), c.prev());
+        c.set(0);
+        assert_eq!(None, c.prev());
+        c.advance(10);
+        assert_eq!(3, c.pos());
+        assert_eq!(None, c.peek());
+        assert_eq!(0, ByteCursor::new(&Rope::from(""), 0).count());
+    }
+
+    #[test]
+    fn byte_cursor_across_leaves() {
+        let (s, r) = long_rope();
+        assert_eq!(s.as_bytes(), &ByteCursor::new(&r, 0).collect::<Vec<_>>()[..]);
+        assert_eq!(s.as_bytes()[1234..], ByteCursor::new(&r, 1234).collect::<Vec<_>>()[..]);
+        let mut c = ByteCursor::new(&r, s.len());
+        let mut backwards = Vec::new();
+        while let Some(b) = c.prev() {
+            backwards.push(b);
+        }
+        backwards.reverse();
+        assert_eq!(s.as_bytes(), &backwards[..]);
+        c.advance(3000);
+        assert_eq!(Some(s.as_bytes()[3000]), c.peek());
+    }
+
+    #[test]
+    fn find_across_leaves() {
+        let (s, r) = long_rope();
+        for &(needle, from) in [(&s[1000..2100], 0), (&s[4990..], 10), ("abc", 0), ("", 17), (&s[20..30], 21)].iter() {
+            assert_eq!(s[from..].find(needle).map(|i| i + from), r.find(needle, from));
+        }
+        assert_eq!(None, r.find("a", 5001));
+    }
+
     #[test]
     fn prev_codepoint_offset_small() {
         let a = Rope::from("a\u{00A1}\u{4E00}\u{1F4A9}");
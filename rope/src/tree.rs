@@ -15,9 +15,13 @@
 //! A general b-tree structure suitable for ropes and the like.
 
 use std::sync::Arc;
+use std::mem;
 use std::cmp::min;
+use std::error::Error;
+use std::fmt;
 
 use interval::Interval;
+use {kmp_table, kmp_find};
 
 const MIN_CHILDREN: usize = 4;
 const MAX_CHILDREN: usize = 8;
@@ -670,6 +674,127 @@ impl<'a, N: NodeInfo> Cursor<'a, N> {
     }
 }
 
+/// A leaf whose elements can be read one at a time as bytes.
+pub trait ByteLeaf: Leaf {
+    fn byte_at(&self, offset: usize) -> u8;
+}
+
+/// A cursor over the bytes of a tree, for scanning it one byte at a time in
+/// either direction.
+///
+/// The cursor keeps the current leaf at hand, so `peek`, `next`, `prev` and
+/// `pos` take constant time except when crossing into another leaf.
+pub struct LeafCursor<'a, N: 'a + NodeInfo> where N::L: ByteLeaf {
+    cursor: Cursor<'a, N>,
+    leaf: Option<&'a N::L>,
+    len_of_leaf: usize,
+    offset_of_leaf: usize,
+    position: usize,
+    len: usize,
+}
+
+impl<'a, N: NodeInfo> LeafCursor<'a, N> where N::L: ByteLeaf {
+    /// Creates a cursor positioned before the byte at `position`.
+    pub fn new(n: &'a Node<N>, position: usize) -> LeafCursor<'a, N> {
+        let mut result = LeafCursor {
+            cursor: Cursor::new(n, 0),
+            leaf: None,
+            len_of_leaf: 0,
+            offset_of_leaf: 0,
+            position: 0,
+            len: n.len(),
+        };
+        result.set(position);
+        result
+    }
+
+    /// The offset of the byte that `next` would return.
+    pub fn pos(&self) -> usize {
+        self.position
+    }
+
+    /// Moves the cursor to `position`, which is clamped to the end.
+    pub fn set(&mut self, position: usize) {
+        self.position = min(position, self.len);
+        if self.position >= self.offset_of_leaf &&
+                self.position < self.offset_of_leaf + self.len_of_leaf {
+            return;
+        }
+        self.cursor.set(self.position);
+        match self.cursor.get_leaf() {
+            Some((l, offset)) if offset < l.len() => {
+                self.set_leaf(Some(l));
+                self.offset_of_leaf = self.position - offset;
+            }
+            _ => {
+                self.set_leaf(None);
+                self.offset_of_leaf = self.position;
+            }
+        }
+    }
+
+    /// Moves forward by `n` bytes, stopping at the end.
+    pub fn advance(&mut self, n: usize) {
+        let position = self.position.saturating_add(n);
+        self.set(position);
+    }
+
+    /// Returns the byte after the cursor without moving it.
+    pub fn peek(&self) -> Option<u8> {
+        let i = self.position.wrapping_sub(self.offset_of_leaf);
+        match self.leaf {
+            Some(l) if i < self.len_of_leaf => Some(l.byte_at(i)),
+            _ => None
+        }
+    }
+
+    /// Moves back one byte and returns it.
+    pub fn prev(&mut self) -> Option<u8> {
+        if self.position == 0 {
+            return None;
+        }
+        let position = self.position - 1;
+        self.set(position);
+        self.peek()
+    }
+
+    fn set_leaf(&mut self, l: Option<&'a N::L>) {
+        self.leaf = l;
+        self.len_of_leaf = l.map_or(0, |l| l.len());
+    }
+
+    // moves to the start of the next leaf, or to the end of the tree
+    fn next_leaf(&mut self) {
+        while self.position < self.len {
+            match self.cursor.next_leaf() {
+                Some((l, _)) if l.len() > 0 => {
+                    self.set_leaf(Some(l));
+                    self.offset_of_leaf = self.position;
+                    return;
+                }
+                Some(_) => {}
+                None => break
+            }
+        }
+        let position = self.position;
+        self.set_leaf(None);
+        self.set(position);
+    }
+}
+
+impl<'a, N: NodeInfo> Iterator for LeafCursor<'a, N> where N::L: ByteLeaf {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.position += 1;
+        if self.position == self.offset_of_leaf + self.len_of_leaf {
+            self.next_leaf();
+        }
+        Some(b)
+    }
+}
+
 /*
 
 // How to access the slice type for a leaf, if available. This will
@@ -680,78 +805,368 @@ fn slice<'a, L: Leaf + Index<RangeFull>>(l: &'a L) -> &'a L::Output {
 }
 */
 
-/*
-// TODO: the following is an example, written during development but
-// not actually used. Either make it real or delete it.
 
+// DNA: a leaf of bases packed four to a byte.
+
+const MIN_DNA_LEAF: usize = 2048;
+const MAX_DNA_LEAF: usize = 4096;
+
+// the bases, indexed by their 2-bit codes
+const BASES: &[u8; 4] = b"ICFP";
+
+fn base_code(b: u8) -> u8 {
+    match b {
+        b'I' => 0,
+        b'C' => 1,
+        b'F' => 2,
+        b'P' => 3,
+        _ => panic!("invalid base '{}'", b as char)
+    }
+}
+
+/// Whether `c` is one of the bases `ICFP`.
+pub fn is_base(c: char) -> bool {
+    matches!(c, 'I' | 'C' | 'F' | 'P')
+}
+
+/// Checks that `s` has nothing but the bases `ICFP`.
+pub fn check_bases(s: &str) -> Result<(), InvalidBase> {
+    match s.char_indices().find(|&(_, c)| !is_base(c)) {
+        Some((offset, found)) => Err(InvalidBase { offset, found }),
+        None => Ok(())
+    }
+}
+
+/// A leaf of DNA bases `I`, `C`, `F` and `P`, stored as 2-bit codes packed
+/// four to a byte, first base in the low bits.
 #[derive(Clone, Default)]
-struct BytesLeaf(Vec<u8>);
+pub struct DnaLeaf {
+    // invariant: the bits after the last base are zero
+    bytes: Vec<u8>,
+    len: usize,
+}
 
-#[derive(Clone)]
-struct BytesInfo(usize);
+impl DnaLeaf {
+    fn code_at(&self, offset: usize) -> u8 {
+        (self.bytes[offset / 4] >> (offset % 4 * 2)) & 3
+    }
+
+    fn push_code(&mut self, code: u8) {
+        if self.len.is_multiple_of(4) {
+            self.bytes.push(code);
+        } else {
+            *self.bytes.last_mut().unwrap() |= code << (self.len % 4 * 2);
+        }
+        self.len += 1;
+    }
 
-// leaf doesn't have to be a newtype
-impl Leaf for Vec<u8> {
+    // appends bases `start..end` of `other`, a byte at a time once aligned
+    fn push_range(&mut self, other: &DnaLeaf, start: usize, end: usize) {
+        let mut i = start;
+        while i < end && !self.len.is_multiple_of(4) {
+            let code = other.code_at(i);
+            self.push_code(code);
+            i += 1;
+        }
+        if i == end {
+            return;
+        }
+        let first = i / 4;
+        let n_bytes = (end - i).div_ceil(4);
+        let shift = i % 4 * 2;
+        if shift == 0 {
+            self.bytes.extend_from_slice(&other.bytes[first..first + n_bytes]);
+        } else {
+            for k in first..first + n_bytes {
+                let high = other.bytes.get(k + 1).map_or(0, |b| b << (8 - shift));
+                self.bytes.push((other.bytes[k] >> shift) | high);
+            }
+        }
+        self.len += end - i;
+        if !self.len.is_multiple_of(4) {
+            *self.bytes.last_mut().unwrap() &= (1 << (self.len % 4 * 2)) - 1;
+        }
+    }
+}
+
+impl ByteLeaf for DnaLeaf {
+    /// Returns the base at `offset` as one of the bytes `ICFP`.
+    fn byte_at(&self, offset: usize) -> u8 {
+        BASES[self.code_at(offset) as usize]
+    }
+}
+
+impl Leaf for DnaLeaf {
     fn len(&self) -> usize {
-        self.len()
+        self.len
     }
 
     fn is_ok_child(&self) -> bool {
-        self.len() >= 512
+        self.len >= MIN_DNA_LEAF
     }
 
-    fn push_maybe_split(&mut self, other: &Vec<u8>, iv: Interval) -> Option<Vec<u8>> {
+    fn push_maybe_split(&mut self, other: &DnaLeaf, iv: Interval) -> Option<DnaLeaf> {
         let (start, end) = iv.start_end();
-        self.extend_from_slice(&other[start..end]);
-        if self.len() <= 1024 {
+        self.push_range(other, start, end);
+        if self.len <= MAX_DNA_LEAF {
             None
         } else {
-            let splitpoint = self.len() / 2;
-            let new = self[splitpoint..].to_owned();
-            self.truncate(splitpoint);
+            // split on a byte boundary so both halves can be copied as is
+            let splitpoint = self.len / 2 / 4 * 4;
+            let new = DnaLeaf {
+                bytes: self.bytes[splitpoint / 4..].to_owned(),
+                len: self.len - splitpoint,
+            };
+            self.bytes.truncate(splitpoint / 4);
+            self.len = splitpoint;
             Some(new)
         }
     }
 }
 
-impl NodeInfo for BytesInfo {
-    type L = Vec<u8>;
-    type BaseMetric = BytesMetric;
-    fn accumulate(&mut self, other: &Self) {
-        self.0 += other.0;
+#[derive(Clone)]
+pub struct DnaInfo(());
+
+impl NodeInfo for DnaInfo {
+    type L = DnaLeaf;
+
+    fn accumulate(&mut self, _: &Self) {}
+
+    fn compute_info(_: &DnaLeaf) -> DnaInfo {
+        DnaInfo(())
     }
+}
+
+/// A rope of DNA bases. It takes a quarter of the memory of a `Rope` of the
+/// same bases, and like `Rope`, clones and slices share storage.
+pub type DnaRope = Node<DnaInfo>;
 
-    fn compute_info(l: &Vec<u8>) -> BytesInfo {
-        BytesInfo(l.len())
+impl DnaRope {
+    /// Returns the base at `offset` as one of the bytes `ICFP`.
+    ///
+    /// Use a `BaseCursor` to read more than a few bases.
+    pub fn byte_at(&self, offset: usize) -> u8 {
+        let cursor = Cursor::new(self, offset);
+        let (leaf, pos) = cursor.get_leaf().unwrap();
+        leaf.byte_at(pos)
+    }
+
+    /// Returns the bases [`start`..`end`) as a new rope, sharing storage
+    /// with `self`.
+    ///
+    /// Time complexity: O(log n)
+    pub fn slice(&self, start: usize, end: usize) -> DnaRope {
+        self.subseq(Interval::new_closed_open(start, end))
+    }
+
+    /// Appends rope `r`.
+    ///
+    /// Time complexity: O(log n)
+    pub fn push(&mut self, r: DnaRope) {
+        let rope = mem::take(self);
+        *self = Node::concat(rope, r);
+    }
+
+    /// Returns the bases in `s` as a rope, or an error if `s` contains
+    /// anything but `ICFP`.
+    pub fn from_bases(s: &str) -> Result<DnaRope, InvalidBase> {
+        check_bases(s)?;
+        let mut b = DnaBuilder::new();
+        b.push_str(s);
+        Ok(b.build())
+    }
+
+    /// Appends the bases in `s`, which must all be one of `ICFP`.
+    pub fn push_str(&mut self, s: &str) {
+        let mut b = DnaBuilder::new();
+        b.push_str(s);
+        self.push(b.build());
+    }
+
+    /// Returns the offset of the first occurrence of the bases `needle`
+    /// starting at or after `from`, or `None` if there is none.
+    ///
+    /// Time complexity: O(n + m) (Knuth-Morris-Pratt)
+    pub fn find(&self, needle: &str, from: usize) -> Option<usize> {
+        if from > self.len() {
+            return None;
+        }
+        let needle = needle.as_bytes();
+        if needle.is_empty() {
+            return Some(from);
+        }
+        kmp_find(needle, &kmp_table(needle), BaseCursor::new(self, from), from)
     }
 }
 
-struct BytesMetric(());
+/// Builds a `DnaRope` a base at a time, packing bases straight into leaves.
+pub struct DnaBuilder {
+    tree: TreeBuilder<DnaInfo>,
+    leaf: DnaLeaf,
+}
+
+impl DnaBuilder {
+    pub fn new() -> DnaBuilder {
+        DnaBuilder { tree: TreeBuilder::new(), leaf: DnaLeaf::default() }
+    }
+
+    /// Appends `b`, which must be one of the bytes `ICFP`.
+    pub fn push_base(&mut self, b: u8) {
+        self.leaf.push_code(base_code(b));
+        if self.leaf.len == MAX_DNA_LEAF {
+            self.flush();
+        }
+    }
+
+    /// Appends the bases in `s`, which must all be one of `ICFP`.
+    pub fn push_str(&mut self, s: &str) {
+        for b in s.bytes() {
+            self.push_base(b);
+        }
+    }
+
+    /// Appends rope `r`, sharing its storage.
+    pub fn push(&mut self, r: DnaRope) {
+        self.flush();
+        self.tree.push(r);
+    }
 
-impl Metric<BytesInfo> for BytesMetric {
-    fn measure(_: &BytesInfo, len: usize) -> usize {
-        len
+    pub fn build(mut self) -> DnaRope {
+        self.flush();
+        self.tree.build()
     }
 
-    fn to_base_units(_: &Vec<u8>, in_measured_units: usize) -> usize {
-        in_measured_units
+    fn flush(&mut self) {
+        if self.leaf.len > 0 {
+            let leaf = mem::take(&mut self.leaf);
+            self.tree.push_leaf(leaf);
+        }
     }
+}
 
-    fn from_base_units(_: &Vec<u8>, in_base_units: usize) -> usize {
-        in_base_units
+impl Default for DnaBuilder {
+    fn default() -> DnaBuilder {
+        DnaBuilder::new()
     }
+}
 
-    fn is_boundary(_: &Vec<u8>, _: usize) -> bool { true }
+/// The error from `DnaRope::from_bases` for a character that is not a base.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidBase {
+    /// The byte offset of the character.
+    pub offset: usize,
+    pub found: char,
+}
 
-    fn prev(_: &Vec<u8>, offset: usize) -> Option<usize> {
-        if offset > 0 { Some(offset - 1) } else { None }
+impl fmt::Display for InvalidBase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid base '{}' at offset {}", self.found, self.offset)
     }
+}
+
+impl Error for InvalidBase {}
 
-    fn next(l: &Vec<u8>, offset: usize) -> Option<usize> {
-        if offset < l.len() { Some(offset + 1) } else { None }
+impl From<DnaRope> for String {
+    fn from(r: DnaRope) -> String {
+        String::from(&r)
     }
+}
 
-    fn can_fragment() -> bool { false }
+impl From<&DnaRope> for String {
+    fn from(r: &DnaRope) -> String {
+        BaseCursor::new(r, 0).map(|b| b as char).collect()
+    }
 }
 
-*/
+/// A cursor over the bases of a `DnaRope`, yielding each base as one of the
+/// bytes `ICFP`.
+pub type BaseCursor<'a> = LeafCursor<'a, DnaInfo>;
+
+impl PartialEq for DnaRope {
+    fn eq(&self, other: &DnaRope) -> bool {
+        self.len() == other.len() && BaseCursor::new(self, 0).eq(BaseCursor::new(other, 0))
+    }
+}
+
+impl Eq for DnaRope {}
+
+#[cfg(test)]
+mod tests {
+    use tree::{DnaRope, DnaBuilder, BaseCursor, InvalidBase};
+
+    fn long_bases() -> String {
+        (0..10000).map(|i| b"ICFP"[i * 7 % 11 % 4] as char).collect()
+    }
+
+    #[test]
+    fn dna_slice_push_small() {
+        let a = DnaRope::from_bases("ICFPPFCI").unwrap();
+        let mut b = a.slice(1, 6);
+        assert_eq!("CFPPF", String::from(&b));
+        b.push(a.slice(0, 3));
+        b.push_str("P");
+        assert_eq!("CFPPFICFP", String::from(&b));
+        assert_eq!(b'P', b.byte_at(3));
+        assert!(b == DnaRope::from_bases("CFPPFICFP").unwrap());
+        assert!(a != b);
+    }
+
+    #[test]
+    fn dna_slice_unaligned() {
+        // slices at every alignment, of a rope spanning several leaves
+        let s = long_bases();
+        let r = DnaRope::from_bases(&s).unwrap();
+        for &(start, end) in &[(0, 10000), (1, 9999), (2, 4099), (3, 8195), (4097, 4098), (5, 5)] {
+            assert_eq!(&s[start..end], String::from(r.slice(start, end)));
+        }
+        let mut joined = r.slice(3, 5001);
+        joined.push(r.slice(5001, 9998));
+        assert_eq!(&s[3..9998], String::from(&joined));
+    }
+
+    #[test]
+    fn dna_builder() {
+        let s = long_bases();
+        let mut b = DnaBuilder::new();
+        b.push_str(&s[..10]);
+        b.push(DnaRope::from_bases(&s[10..6000]).unwrap());
+        for &c in &s.as_bytes()[6000..] {
+            b.push_base(c);
+        }
+        assert_eq!(s, String::from(b.build()));
+    }
+
+    #[test]
+    fn base_cursor() {
+        let s = long_bases();
+        let mut r = DnaRope::from_bases(&s[..3001]).unwrap();
+        r.push(DnaRope::from_bases(&s[3001..]).unwrap());
+        let mut c = BaseCursor::new(&r, 2999);
+        assert_eq!(Some(s.as_bytes()[2999]), c.peek());
+        assert_eq!(&s[2999..], String::from_utf8(c.by_ref().collect()).unwrap());
+        assert_eq!(10000, c.pos());
+        assert_eq!(Some(s.as_bytes()[9999]), c.prev());
+        c.set(4096);
+        assert_eq!(Some(s.as_bytes()[4096]), c.next());
+        c.advance(20000);
+        assert_eq!(None, c.next());
+    }
+
+    #[test]
+    fn dna_find() {
+        let s = long_bases();
+        let r = DnaRope::from_bases(&s).unwrap();
+        let needle = &s[4090..4110];
+        assert_eq!(s.find(needle), r.find(needle, 0));
+        assert_eq!(s[4091..].find(needle).map(|i| i + 4091), r.find(needle, 4091));
+        assert_eq!(None, DnaRope::from_bases("ICFP").unwrap().find("CFF", 0));
+    }
+
+    #[test]
+    fn dna_from_bases() {
+        assert_eq!("", String::from(DnaRope::from_bases("").unwrap()));
+        let e = DnaRope::from_bases("ICFéPX").err().unwrap();
+        assert_eq!(InvalidBase { offset: 3, found: 'é' }, e);
+        assert_eq!("invalid base 'é' at offset 3", e.to_string());
+    }
+}
@@ -0,0 +1,147 @@
+//! Writing animated PNGs.
+//!
+//! Each frame is compressed by the `image` crate's PNG encoder, and its
+//! image data is moved into the frame chunks of an APNG:
+//!
+//! ```text
+//! signature IHDR acTL (fcTL IDAT) (fcTL fdAT)* IEND
+//! ```
+//!
+//! Viewers without APNG support show the first frame.
+
+use std::io;
+use std::io::prelude::*;
+use image::ColorType;
+use image::png::PNGEncoder;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    w.write_all(&body)?;
+    w.write_all(&crc32(&body).to_be_bytes())
+}
+
+/// The chunks of a PNG file, as (type, data) pairs.
+fn chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut i = SIGNATURE.len();
+    while i + 8 <= png.len() {
+        let len = u32::from_be_bytes([png[i], png[i + 1], png[i + 2], png[i + 3]]) as usize;
+        let kind = [png[i + 4], png[i + 5], png[i + 6], png[i + 7]];
+        chunks.push((kind, &png[i + 8..i + 8 + len]));
+        i += 12 + len;
+    }
+    chunks
+}
+
+/// An animation of RGB frames, kept compressed until it is written.
+pub struct Apng {
+    width: u32,
+    height: u32,
+    ihdr: Vec<u8>,
+    // the image data and delay in milliseconds of each frame
+    frames: Vec<(Vec<u8>, u16)>
+}
+
+impl Apng {
+    pub fn new(width: u32, height: u32) -> Apng {
+        Apng { width, height, ihdr: Vec::new(), frames: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Adds a frame of `width * height` RGB pixels, shown for `delay`
+    /// milliseconds.
+    pub fn add_frame(&mut self, rgb: &[u8], delay: u16) -> io::Result<()> {
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png).encode(rgb, self.width, self.height, ColorType::RGB(8))?;
+        let mut data = Vec::new();
+        for (kind, chunk) in chunks(&png) {
+            match &kind {
+                b"IHDR" => self.ihdr = chunk.to_vec(),
+                b"IDAT" => data.extend_from_slice(chunk),
+                _ => {}
+            }
+        }
+        self.frames.push((data, delay));
+        Ok(())
+    }
+
+    /// Changes how long the last frame is shown.
+    pub fn set_last_delay(&mut self, delay: u16) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.1 = delay;
+        }
+    }
+
+    /// Writes the animation, looping forever.
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&SIGNATURE)?;
+        write_chunk(w, b"IHDR", &self.ihdr)?;
+        let mut actl = (self.frames.len() as u32).to_be_bytes().to_vec();
+        actl.extend_from_slice(&0u32.to_be_bytes());
+        write_chunk(w, b"acTL", &actl)?;
+        let mut sequence = 0u32;
+        for (i, &(ref data, delay)) in self.frames.iter().enumerate() {
+            let mut fctl = Vec::new();
+            for &n in [sequence, self.width, self.height, 0, 0].iter() {
+                fctl.extend_from_slice(&n.to_be_bytes());
+            }
+            fctl.extend_from_slice(&delay.to_be_bytes());
+            fctl.extend_from_slice(&1000u16.to_be_bytes());
+            // no disposal, and the frame replaces the canvas
+            fctl.extend_from_slice(&[0, 0]);
+            write_chunk(w, b"fcTL", &fctl)?;
+            sequence += 1;
+            if i == 0 {
+                write_chunk(w, b"IDAT", data)?;
+            } else {
+                let mut fdat = sequence.to_be_bytes().to_vec();
+                fdat.extend_from_slice(data);
+                write_chunk(w, b"fdAT", &fdat)?;
+                sequence += 1;
+            }
+        }
+        write_chunk(w, b"IEND", &[])
+    }
+}
+
+#[test]
+fn apng_test() {
+    assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    let mut apng = Apng::new(2, 1);
+    apng.add_frame(&[255, 0, 0, 0, 0, 255], 40).unwrap();
+    apng.add_frame(&[0, 255, 0, 0, 0, 255], 2000).unwrap();
+    let mut out = Vec::new();
+    apng.write(&mut out).unwrap();
+    assert_eq!(&SIGNATURE, &out[..8]);
+    let kinds = chunks(&out).iter().map(|&(kind, _)| String::from_utf8(kind.to_vec()).unwrap()).collect::<Vec<_>>();
+    assert_eq!(vec!["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"], kinds);
+    let chunks = chunks(&out);
+    // the frame count, then the second frame's fcTL and fdAT numbered after
+    // the first fcTL, with a 2000/1000s delay
+    assert_eq!(&[0, 0, 0, 2], &chunks[1].1[..4]);
+    assert_eq!(&[0, 0, 0, 1], &chunks[4].1[..4]);
+    assert_eq!(&[0x07, 0xd0, 0x03, 0xe8], &chunks[4].1[20..24]);
+    assert_eq!(&[0, 0, 0, 2], &chunks[5].1[..4]);
+}
@@ -0,0 +1,201 @@
+//! Assembling DNA from the textual notation for patterns and templates.
+//!
+//! This is the notation printed by `dna::pattern_to_string` and
+//! `dna::template_to_string`, as seen in `-l` logs and `endo.trace`:
+//!
+//! * pattern: bases `ICFP`, `!n` to skip `n` bases, `?"bases"` to search,
+//!   and `(` `)` to capture;
+//! * template: bases `ICFP`, `\n` for capture `n`, `\n(l)` for capture `n`
+//!   protected `l` times, and `|n|` for the length of capture `n`.
+//!
+//! A source file has one instruction per line, written `PATTERN -> TEMPLATE`
+//! as in `disasm` listings, optionally preceded by RNA commands to emit in
+//! square brackets.  Whitespace is ignored and `#` starts a comment:
+//!
+//! ```text
+//! # page 3 of the repair guide
+//! (?"IFPCFFP")IIIIIIIIIIIIIIIIIIIIIII -> \0CCIIIIIIIIIIIIIIIIIIIII
+//! [PIIIIIP] !0 -> \0(1)
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use dna::{PItem, TItem};
+use rna::RnaCommand;
+use xi_rope::tree::{is_base, check_bases};
+use prefix;
+use {invalid, number};
+
+fn read_number(chars: &mut Peekable<Chars>) -> io::Result<usize> {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+    number(&digits)
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> io::Result<()> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => Err(invalid(format!("expected '{}', found '{}'", expected, c))),
+        None => Err(invalid(format!("expected '{}', found end of input", expected)))
+    }
+}
+
+/// Parses a pattern written in the notation of `dna::pattern_to_string`.
+pub fn parse_pattern(s: &str) -> io::Result<Vec<PItem>> {
+    let src = s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let mut chars = src.chars().peekable();
+    let mut items = Vec::new();
+    let mut level = 0usize;
+    loop {
+        let item = match chars.next() {
+            None => break,
+            Some('!') => PItem::Skip(read_number(&mut chars)?),
+            Some('?') => {
+                expect(&mut chars, '"')?;
+                let mut bases = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) if is_base(c) => bases.push(c),
+                        None => return Err(invalid(String::from("unterminated search"))),
+                        Some(c) => return Err(invalid(format!("invalid base '{}' in search", c)))
+                    }
+                }
+                PItem::Search(bases)
+            },
+            Some('(') => {
+                level += 1;
+                PItem::Open
+            },
+            Some(')') => {
+                if level == 0 {
+                    return Err(invalid(String::from("unmatched ')'")));
+                }
+                level -= 1;
+                PItem::Close
+            },
+            Some(c) if is_base(c) => PItem::Base(c),
+            Some(c) => return Err(invalid(format!("unexpected '{}' in pattern", c)))
+        };
+        if let (Some(&PItem::Search(_)), &PItem::Base(_)) = (items.last(), &item) {
+            return Err(invalid(String::from("a base cannot directly follow a search; it would be read as part of it")));
+        }
+        items.push(item);
+    }
+    if level > 0 {
+        return Err(invalid(String::from("unmatched '('")));
+    }
+    Ok(items)
+}
+
+/// Parses a template written in the notation of `dna::template_to_string`.
+pub fn parse_template(s: &str) -> io::Result<Vec<TItem>> {
+    let src = s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let mut chars = src.chars().peekable();
+    let mut items = Vec::new();
+    loop {
+        let item = match chars.next() {
+            None => break,
+            Some('\\') => {
+                let n = read_number(&mut chars)?;
+                if chars.peek() == Some(&'(') {
+                    chars.next();
+                    let l = read_number(&mut chars)?;
+                    expect(&mut chars, ')')?;
+                    TItem::Reference(n, l)
+                } else {
+                    TItem::Reference(n, 0)
+                }
+            },
+            Some('|') => {
+                let n = read_number(&mut chars)?;
+                expect(&mut chars, '|')?;
+                TItem::Length(n)
+            },
+            Some(c) if is_base(c) => TItem::Base(c),
+            Some(c) => return Err(invalid(format!("unexpected '{}' in template", c)))
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Assembles a single `PATTERN -> TEMPLATE` instruction, with optional
+/// leading `[bases]` RNA commands, into DNA.
+pub fn assemble_instruction(line: &str) -> io::Result<String> {
+    let mut line = line.trim();
+    let mut dna = String::new();
+    while line.starts_with('[') {
+        let end = line.find(']').ok_or_else(|| invalid(String::from("unterminated RNA command")))?;
+        let bases = &line[1..end];
+        if bases.len() != 7 || check_bases(bases).is_err() {
+            return Err(invalid(format!("RNA command '{}' is not 7 bases", bases)));
+        }
+        dna += &prefix::rna(&RnaCommand::from(bases));
+        line = line[end + 1..].trim_start();
+    }
+    let mut parts = line.splitn(2, "->");
+    let pattern = parse_pattern(parts.next().unwrap_or(""))?;
+    let template = match parts.next() {
+        Some(t) => parse_template(t)?,
+        None => return Err(invalid(String::from("expected 'PATTERN -> TEMPLATE'")))
+    };
+    Ok(dna + &prefix::pattern(&pattern) + &prefix::template(&template))
+}
+
+/// Assembles a source text of instructions, one per line, into DNA.
+pub fn assemble(text: &str) -> io::Result<String> {
+    let mut dna = String::new();
+    for (n, code) in prefix::code_lines(text) {
+        match assemble_instruction(code) {
+            Ok(bases) => dna += &bases,
+            Err(e) => return Err(invalid(format!("line {}: {}", n, e)))
+        }
+    }
+    Ok(dna)
+}
+
+/// Assembles the source file at `path` into DNA.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    assemble(&text)
+}
+
+#[test]
+fn round_trip_test() {
+    use dna::{pattern_to_string, template_to_string};
+    let patterns = ["!0(!4536645(!800)!2971964)", "(!7509409?\"IFPICFPPCIFF\")(!1)(IFPICFPPCIFP)", "(?\"IFPP\")F", ""];
+    for &p in patterns.iter() {
+        assert_eq!(p, pattern_to_string(&parse_pattern(p).unwrap()));
+    }
+    let templates = ["\\0\\1IIIIIIIIIIIIIIIIIIIIIIIP", "IPCCIICICIIIICIIIIIIIIIIIPIICIIC\\0", "\\0(2)|1|\\12C", ""];
+    for &t in templates.iter() {
+        assert_eq!(t, template_to_string(&parse_template(t).unwrap()));
+    }
+}
+
+#[test]
+fn assemble_test() {
+    assert_eq!("IIPIFFCPICICIICPIICIPPPICIIC", assemble("(?\"IFPP\")F -> \\0P").unwrap());
+    assert_eq!(prefix::repair_guide_page(3), assemble("# page 3\n(?\"IFPCFFP\") IIIIIIIIIIIIIIIIIIIIIII -> \\0 CCIIIIIIIIIIIIIIIIIIIII\n").unwrap());
+    assert_eq!("IIIPIIIIIPIPPIICIPCPPIIC", assemble("[PIIIIIP] !0 -> \\0(1)").unwrap());
+    let dna = assemble("(!7509409?\"IFPICFPPCIFF\")(!1)(IFPICFPPCIFP) -> \\0\\1\\1(2)|1|\\2").unwrap();
+    let decoded = ::dna::decode(&::xi_rope::tree::DnaRope::from_bases(&dna).unwrap()).unwrap();
+    assert_eq!(parse_pattern("(!7509409?\"IFPICFPPCIFF\")(!1)(IFPICFPPCIFP)").unwrap(), decoded.pattern);
+    assert_eq!(parse_template("\\0\\1\\1(2)|1|\\2").unwrap(), decoded.template);
+    assert_eq!(dna.len(), decoded.length);
+    assert!(assemble("(!1 -> \\0").is_err());
+    assert!(assemble("?\"IF\"C -> ").is_err());
+    assert!(assemble("(!1)").is_err());
+}
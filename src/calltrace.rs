@@ -0,0 +1,193 @@
+//! Tracing Fuun gene calls through their RNA markers.
+//!
+//! Many genes start by emitting an RNA command that begins with `C`, taken
+//! from bases 3..10 of the gene, and emit `CFPICFP` when they return.
+//! Following these markers gives a tree of calls, each spanning the
+//! iterations between its two markers.  Markers are named after the gene
+//! in the gene table whose `code` they match.
+//!
+//! The tree can be written as nested JSON, or as folded stacks for
+//! flamegraph tools, weighted by the iterations spent in each call itself:
+//!
+//! ```text
+//! balloon;colorByIndex 1200
+//! balloon 85
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::prelude::*;
+use dna::json_string;
+use gene::Gene;
+use rna::RnaCommand;
+
+/// The marker emitted when a gene returns.
+pub const RETURN_MARKER: &str = "CFPICFP";
+
+/// A call of a gene, from the iteration that emitted its marker to the one
+/// that emitted the return marker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Call {
+    pub marker: String,
+    pub name: Option<&'static str>,
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<Call>
+}
+
+impl Call {
+    /// The gene name, or the marker if the gene is unknown.
+    pub fn label(&self) -> &str {
+        self.name.unwrap_or(&self.marker)
+    }
+}
+
+/// A change in the call stack, as seen by `CallTrace::observe`.
+pub enum Event<'a> {
+    /// A call started at the given depth.
+    Enter(usize, &'a Call),
+    /// A call at the given depth returned.
+    Return(usize, &'a Call)
+}
+
+/// Builds the tree of calls from the RNA emitted by a run.
+pub struct CallTrace {
+    names: HashMap<String, &'static str>,
+    // the calls in progress, outermost first
+    stack: Vec<Call>,
+    calls: Vec<Call>
+}
+
+impl CallTrace {
+    /// Creates a trace that names markers after `genes`.
+    pub fn new(genes: &[Gene]) -> CallTrace {
+        let mut names = HashMap::new();
+        for gene in genes {
+            if let Some(ref code) = gene.code {
+                names.entry(code.clone()).or_insert(gene.name);
+            }
+        }
+        CallTrace { names, stack: Vec::new(), calls: Vec::new() }
+    }
+
+    /// Follows an RNA command emitted in `iteration`.
+    pub fn observe(&mut self, iteration: usize, r: &RnaCommand) -> Option<Event<'_>> {
+        let marker = match *r {
+            RnaCommand::Unknown(ref marker) => marker,
+            _ => return None
+        };
+        if marker == RETURN_MARKER {
+            let mut call = self.stack.pop()?;
+            call.end = iteration;
+            let depth = self.stack.len();
+            let siblings = match self.stack.last_mut() {
+                Some(parent) => &mut parent.children,
+                None => &mut self.calls
+            };
+            siblings.push(call);
+            Some(Event::Return(depth, siblings.last().unwrap()))
+        } else if marker.starts_with('C') {
+            let name = self.names.get(marker).cloned();
+            self.stack.push(Call { marker: marker.clone(), name, start: iteration, end: iteration, children: Vec::new() });
+            Some(Event::Enter(self.stack.len() - 1, self.stack.last().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    /// The tree of calls, with any calls still in progress ending at
+    /// `iteration`.
+    pub fn into_calls(mut self, iteration: usize) -> Vec<Call> {
+        while let Some(mut call) = self.stack.pop() {
+            call.end = iteration;
+            match self.stack.last_mut() {
+                Some(parent) => parent.children.push(call),
+                None => self.calls.push(call)
+            }
+        }
+        self.calls
+    }
+}
+
+fn write_call<W: Write>(call: &Call, w: &mut W) -> io::Result<()> {
+    let name = call.name.map_or(String::from("null"), json_string);
+    write!(w, "{{\"name\":{},\"marker\":{},\"start\":{},\"end\":{},\"children\":[",
+        name, json_string(&call.marker), call.start, call.end)?;
+    for (i, child) in call.children.iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        write_call(child, w)?;
+    }
+    write!(w, "]}}")
+}
+
+/// Writes `calls` as a JSON array of objects with the gene `name` (`null`
+/// if unknown), `marker`, `start` and `end` iterations, and `children`.
+pub fn write_json<W: Write>(calls: &[Call], w: &mut W) -> io::Result<()> {
+    write!(w, "[")?;
+    for (i, call) in calls.iter().enumerate() {
+        if i > 0 {
+            write!(w, ",")?;
+        }
+        write_call(call, w)?;
+    }
+    writeln!(w, "]")
+}
+
+fn fold(call: &Call, prefix: &str, stacks: &mut BTreeMap<String, usize>) {
+    let stack = if prefix.is_empty() { call.label().to_string() } else { format!("{};{}", prefix, call.label()) };
+    let children = call.children.iter().map(|c| c.end - c.start).sum::<usize>();
+    *stacks.entry(stack.clone()).or_insert(0) += (call.end - call.start).saturating_sub(children);
+    for child in &call.children {
+        fold(child, &stack, stacks);
+    }
+}
+
+/// Writes `calls` as folded stacks, one `outer;inner iterations` line per
+/// distinct stack, counting the iterations spent in the innermost call
+/// itself.
+pub fn write_folded<W: Write>(calls: &[Call], w: &mut W) -> io::Result<()> {
+    let mut stacks = BTreeMap::new();
+    for call in calls {
+        fold(call, "", &mut stacks);
+    }
+    for (stack, iterations) in stacks {
+        if iterations > 0 {
+            writeln!(w, "{} {}", stack, iterations)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn call_trace_test() {
+    let genes = [Gene { name: "apple", offset: 0, length: 0, code: Some(String::from("CFPIIII")) }];
+    let mut trace = CallTrace::new(&genes);
+    let ret = RnaCommand::from(RETURN_MARKER);
+    let events = [
+        (2, RnaCommand::from("CFPIIII")), (3, RnaCommand::Move), (5, RnaCommand::from("CCCCCCC")),
+        (9, ret.clone()), (10, RnaCommand::from("CFPIIII")), (12, ret.clone()), (20, ret.clone()),
+        (21, ret.clone()), (25, RnaCommand::from("CCCCCCC"))
+    ];
+    let mut depths = Vec::new();
+    for &(iteration, ref r) in events.iter() {
+        match trace.observe(iteration, r) {
+            Some(Event::Enter(depth, call)) => depths.push(format!("+{} {}", depth, call.label())),
+            Some(Event::Return(depth, call)) => depths.push(format!("-{} {}", depth, call.label())),
+            None => {}
+        }
+    }
+    assert_eq!(vec!["+0 apple", "+1 CCCCCCC", "-1 CCCCCCC", "+1 apple", "-1 apple", "-0 apple", "+0 CCCCCCC"], depths);
+    let calls = trace.into_calls(30);
+    assert_eq!(2, calls.len());
+    assert_eq!((2, 20, 2), (calls[0].start, calls[0].end, calls[0].children.len()));
+    assert_eq!((25, 30), (calls[1].start, calls[1].end));
+
+    let mut json = Vec::new();
+    write_json(&calls[1..], &mut json).unwrap();
+    assert_eq!("[{\"name\":null,\"marker\":\"CCCCCCC\",\"start\":25,\"end\":30,\"children\":[]}]\n", String::from_utf8(json).unwrap());
+    let mut folded = Vec::new();
+    write_folded(&calls, &mut folded).unwrap();
+    assert_eq!("CCCCCCC 5\napple 12\napple;CCCCCCC 4\napple;apple 2\n", String::from_utf8(folded).unwrap());
+}
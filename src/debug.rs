@@ -0,0 +1,367 @@
+//! An interactive debugger for DNA execution.
+//!
+//! The debugger wraps a `DnaMachine` and reads one command per line:
+//!
+//! ```text
+//! break iter N         stop once N iterations have run
+//! break rna BASES      stop after a step emits the RNA command BASES
+//! break prefix BASES   stop when the DNA starts with BASES
+//! break len >N         stop when the DNA is longer than N bases (or <N)
+//! delete N             remove breakpoint N
+//! breaks               list the breakpoints
+//! step [N]             run N iterations (default 1), ignoring breakpoints
+//! continue             run until a breakpoint fires or the machine stops
+//! back [N]             go back N iterations (default 1)
+//! goto N               go back or forward to the start of iteration N
+//! history [N]          show the DNA length and RNA count of the last N
+//!                      iterations (default 10)
+//! next                 show the pattern and template about to run
+//! env                  show what the next pattern matches and captures
+//! dna START [END]      print bases START..END of the DNA
+//! info                 show the iteration, DNA length and RNA count
+//! quit
+//! ```
+//!
+//! Commands can be shortened to their first letter, e.g. `b iter 100`.
+//! Every step is kept in a `History`, which is what `back` and `goto` use
+//! to travel back in time.
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use xi_rope::tree::{DnaRope, BaseCursor, check_bases};
+use dna::{self, DnaMachine, DnaError, Instruction};
+use history::History;
+use rna::RnaCommand;
+use {invalid, number};
+
+const HELP: &str = "commands: break iter|rna|prefix|len ARG, delete N, breaks, step [N], continue, back [N], goto N, history [N], next, env, dna START [END], info, quit";
+
+/// Iterations between the checkpoints kept by a new `Debugger`.
+pub const CHECKPOINT_INTERVAL: usize = 1000;
+
+fn bases(s: &str) -> io::Result<&str> {
+    check_bases(s).map_err(|e| invalid(e.to_string()))?;
+    Ok(s)
+}
+
+/// A condition checked after every step of `Debugger::resume`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The given number of iterations have run.
+    Iteration(usize),
+    /// The step emitted this RNA command.
+    Rna(RnaCommand),
+    /// The DNA starts with these bases.
+    Prefix(String),
+    /// The DNA is longer than this many bases.
+    LongerThan(usize),
+    /// The DNA is shorter than this many bases.
+    ShorterThan(usize)
+}
+
+impl Breakpoint {
+    /// Parses the arguments of a `break` command, e.g. `len >100000`.
+    pub fn parse(s: &str) -> io::Result<Breakpoint> {
+        let mut words = s.split_whitespace();
+        let kind = words.next().unwrap_or("");
+        let arg = words.collect::<String>();
+        match kind {
+            "iter" => Ok(Breakpoint::Iteration(number(&arg)?)),
+            "rna" if arg.len() == 7 => Ok(Breakpoint::Rna(RnaCommand::from(bases(&arg)?))),
+            "rna" => Err(invalid(format!("RNA command '{}' is not 7 bases", arg))),
+            "prefix" if !arg.is_empty() => Ok(Breakpoint::Prefix(String::from(bases(&arg)?))),
+            "len" if arg.starts_with('>') => Ok(Breakpoint::LongerThan(number(&arg[1..])?)),
+            "len" if arg.starts_with('<') => Ok(Breakpoint::ShorterThan(number(&arg[1..])?)),
+            _ => Err(invalid(format!("expected 'iter N', 'rna BASES', 'prefix BASES' or 'len >N' or 'len <N', found '{}'", s.trim())))
+        }
+    }
+
+    /// Whether the breakpoint fires after a step that emitted `new_rna`.
+    pub fn hit(&self, machine: &DnaMachine, new_rna: &[RnaCommand]) -> bool {
+        match *self {
+            Breakpoint::Iteration(n) => machine.iteration == n,
+            Breakpoint::Rna(ref r) => new_rna.contains(r),
+            Breakpoint::Prefix(ref p) => starts_with(&machine.dna, p),
+            Breakpoint::LongerThan(n) => machine.dna.len() > n,
+            Breakpoint::ShorterThan(n) => machine.dna.len() < n
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Breakpoint::Iteration(n) => write!(f, "iter {}", n),
+            Breakpoint::Rna(ref r) => write!(f, "rna {}", r),
+            Breakpoint::Prefix(ref p) => write!(f, "prefix {}", p),
+            Breakpoint::LongerThan(n) => write!(f, "len >{}", n),
+            Breakpoint::ShorterThan(n) => write!(f, "len <{}", n)
+        }
+    }
+}
+
+fn starts_with(dna: &DnaRope, prefix: &str) -> bool {
+    prefix.len() <= dna.len() && BaseCursor::new(dna, 0).zip(prefix.bytes()).all(|(a, b)| a == b)
+}
+
+pub struct Debugger {
+    pub machine: DnaMachine,
+    pub breakpoints: Vec<Breakpoint>,
+    pub history: History
+}
+
+impl Debugger {
+    pub fn new(machine: DnaMachine) -> Debugger {
+        Debugger { machine, breakpoints: Vec::new(), history: History::new(CHECKPOINT_INTERVAL) }
+    }
+
+    /// Runs until a breakpoint fires, returning its index, or until the
+    /// machine stops.
+    pub fn resume(&mut self) -> Option<usize> {
+        loop {
+            let rna_count = self.machine.rna.len();
+            if !self.history.step(&mut self.machine) {
+                return None;
+            }
+            let machine = &self.machine;
+            let new_rna = &machine.rna[rna_count..];
+            if let Some(i) = self.breakpoints.iter().position(|b| b.hit(machine, new_rna)) {
+                return Some(i);
+            }
+        }
+    }
+
+    /// Reads commands from `r` until `quit` or the end of the input,
+    /// prompting and answering on `w`.
+    pub fn run<R: BufRead, W: Write>(&mut self, r: R, mut w: W) -> io::Result<()> {
+        self.status(&mut w)?;
+        let mut lines = r.lines();
+        loop {
+            write!(w, "(endo) ")?;
+            w.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return writeln!(w)
+            };
+            match self.command(&line, &mut w) {
+                Ok(true) => {},
+                Ok(false) => return Ok(()),
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => writeln!(w, "error: {}", e)?,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    /// Runs a single command, returning false for `quit`.
+    pub fn command<W: Write>(&mut self, line: &str, w: &mut W) -> io::Result<bool> {
+        let line = line.trim();
+        let (name, args) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, "")
+        };
+        match name {
+            "" => {},
+            "b" | "break" => {
+                self.breakpoints.push(Breakpoint::parse(args)?);
+                writeln!(w, "breakpoint {}: {}", self.breakpoints.len() - 1, self.breakpoints.last().unwrap())?;
+            },
+            "d" | "delete" => {
+                let n = number(args)?;
+                if n >= self.breakpoints.len() {
+                    return Err(invalid(format!("no breakpoint {}", n)));
+                }
+                self.breakpoints.remove(n);
+            },
+            "breaks" => {
+                for (i, b) in self.breakpoints.iter().enumerate() {
+                    writeln!(w, "{:4}  {}", i, b)?;
+                }
+            },
+            "s" | "step" => {
+                let n = if args.is_empty() { 1 } else { number(args)? };
+                for _ in 0..n {
+                    if !self.history.step(&mut self.machine) {
+                        break;
+                    }
+                }
+                self.status(w)?;
+            },
+            "c" | "continue" => {
+                if let Some(i) = self.resume() {
+                    writeln!(w, "stopped at breakpoint {}: {}", i, self.breakpoints[i])?;
+                }
+                self.status(w)?;
+            },
+            "back" => {
+                let n = if args.is_empty() { 1 } else { number(args)? };
+                let iteration = self.machine.iteration.saturating_sub(n);
+                self.goto(iteration, w)?;
+            },
+            "g" | "goto" => {
+                let iteration = number(args)?;
+                self.goto(iteration, w)?;
+            },
+            "history" => {
+                let n = if args.is_empty() { 10 } else { number(args)? };
+                let end = self.machine.iteration;
+                for i in end.saturating_sub(n)..end {
+                    if let Some(r) = self.history.record(i) {
+                        writeln!(w, "{:8}  {} bases of DNA, {} RNA commands", i, r.dna_length, r.rna_count)?;
+                    }
+                }
+            },
+            "n" | "next" => self.next(w)?,
+            "e" | "env" => self.env(w)?,
+            "dna" => {
+                let mut words = args.split_whitespace();
+                let start = number(words.next().unwrap_or(""))?;
+                let end = match words.next() {
+                    Some(end) => number(end)?,
+                    None => start.saturating_add(60)
+                };
+                let len = self.machine.dna.len();
+                let (start, end) = (start.min(len), end.min(len).max(start.min(len)));
+                writeln!(w, "{:8}  {}", start, String::from(self.machine.dna.slice(start, end)))?;
+            },
+            "i" | "info" => self.status(w)?,
+            "q" | "quit" => return Ok(false),
+            "h" | "help" => writeln!(w, "{}", HELP)?,
+            _ => return Err(invalid(format!("unknown command '{}'; {}", name, HELP)))
+        }
+        Ok(true)
+    }
+
+    fn goto<W: Write>(&mut self, iteration: usize, w: &mut W) -> io::Result<()> {
+        if !self.history.goto(&mut self.machine, iteration) {
+            writeln!(w, "cannot reach iteration {}", iteration)?;
+        }
+        self.status(w)
+    }
+
+    fn status<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let machine = &self.machine;
+        writeln!(w, "iteration {}: {} bases of DNA, {} RNA commands", machine.iteration, machine.dna.len(), machine.rna.len())?;
+        if let Some(limit) = machine.limit() {
+            writeln!(w, "stopped: {}", limit)?;
+        } else if let Some(e) = machine.error() {
+            writeln!(w, "stopped: {}", e)?;
+        } else if machine.is_finished() {
+            writeln!(w, "DNA finished")?;
+        } else {
+            self.next(w)?;
+        }
+        Ok(())
+    }
+
+    // decodes the instruction about to run; `None` if the DNA is finished
+    fn decode(&self) -> Result<Option<Instruction>, DnaError> {
+        let machine = &self.machine;
+        if machine.dna.len() == 0 {
+            return Ok(None);
+        }
+        dna::decode(&machine.dna).map(Some).map_err(|mut e| {
+            e.iteration = machine.iteration;
+            e
+        })
+    }
+
+    fn next<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self.decode() {
+            Ok(Some(instruction)) => write_instruction(&instruction, w),
+            Ok(None) => writeln!(w, "DNA finished"),
+            Err(e) => writeln!(w, "next: {}", e)
+        }
+    }
+
+    fn env<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let instruction = match self.decode() {
+            Ok(Some(instruction)) => instruction,
+            Ok(None) => return writeln!(w, "DNA finished"),
+            Err(e) => return writeln!(w, "next: {}", e)
+        };
+        match dna::matches(&instruction.pattern, &self.machine.dna, instruction.length) {
+            Some((i, e)) => {
+                writeln!(w, "match of length {}", i)?;
+                for (i, captured) in e.iter().enumerate() {
+                    writeln!(w, "e[{}] = {}", i, dna::dna_to_string(captured))?;
+                }
+                Ok(())
+            },
+            None => writeln!(w, "no match")
+        }
+    }
+}
+
+fn write_instruction<W: Write>(instruction: &Instruction, w: &mut W) -> io::Result<()> {
+    writeln!(w, "next: {} -> {}", dna::pattern_to_string(&instruction.pattern), dna::template_to_string(&instruction.template))?;
+    for r in &instruction.rna {
+        writeln!(w, "      rna {}", r)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn breakpoint_test() {
+    assert_eq!(Breakpoint::Iteration(12), Breakpoint::parse("iter 12").unwrap());
+    assert_eq!(Breakpoint::Rna(RnaCommand::Move), Breakpoint::parse(" rna PIIIIIP").unwrap());
+    assert_eq!(Breakpoint::Prefix(String::from("IIPC")), Breakpoint::parse("prefix IIPC").unwrap());
+    assert_eq!(Breakpoint::LongerThan(5), Breakpoint::parse("len > 5").unwrap());
+    assert_eq!("len <5", Breakpoint::parse("len <5").unwrap().to_string());
+    assert!(Breakpoint::parse("rna PIIIIIX").is_err());
+    assert!(Breakpoint::parse("len 5").is_err());
+}
+
+#[test]
+fn session_test() {
+    // emits PIPIIPC, then PIIIIIP from `(I) -> \0`, then PIPIIPF
+    let dna = "IIIPIPIIPCIICIIC".to_string() + "IIIPIIIIIPIIPCIICIICIPPPIIC" + "IIIPIPIIPFIICIICFFF";
+    let mut debugger = Debugger::new(DnaMachine::new(DnaRope::from_bases(&dna).unwrap()));
+    let input = "break rna PIIIIIP\nbreak prefix IIIPIIIIIP\nc\nenv\nc\ndna 0 4\nbreaks\nd 0\nc\nstep\nbogus\nhistory 2\nback 2\ngoto 9\n";
+    let mut out = Vec::new();
+    debugger.run(input.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let expected = [
+        "iteration 0: 62 bases of DNA, 0 RNA commands\nnext:  -> \n      rna PIPIIPC\n",
+        "stopped at breakpoint 1: prefix IIIPIIIIIP\niteration 1: 46 bases of DNA, 1 RNA commands\nnext: (I) -> \\0\n      rna PIIIIIP\n",
+        "(endo) match of length 1\ne[0] = I (1 bases)\n",
+        "(endo) stopped at breakpoint 0: rna PIIIIIP\niteration 2: 19 bases of DNA, 2 RNA commands\n",
+        "(endo)        0  IIIP\n",
+        "(endo)    0  rna PIIIIIP\n   1  prefix IIIPIIIIIP\n",
+        "iteration 3: 3 bases of DNA, 3 RNA commands\nstopped: DNA ran out while decoding a pattern at offset 3 in iteration 3\n",
+        "(endo) error: unknown command 'bogus'",
+        "(endo)        1  46 bases of DNA, 1 RNA commands\n       2  19 bases of DNA, 2 RNA commands\n",
+        "(endo) iteration 1: 46 bases of DNA, 1 RNA commands\nnext: (I) -> \\0\n",
+        "(endo) cannot reach iteration 9\niteration 3: 3 bases of DNA, 3 RNA commands\n"
+    ];
+    for part in expected.iter() {
+        assert!(out.contains(part), "missing {:?} in\n{}", part, out);
+    }
+}
+
+#[test]
+fn next_test() {
+    // empty DNA is finished, not a pattern that ran out
+    let mut debugger = Debugger::new(DnaMachine::new(DnaRope::default()));
+    let mut out = Vec::new();
+    debugger.run("next\nenv\n".as_bytes(), &mut out).unwrap();
+    assert_eq!("iteration 0: 0 bases of DNA, 0 RNA commands\nDNA finished\n(endo) DNA finished\n(endo) DNA finished\n(endo) \n",
+               String::from_utf8(out).unwrap());
+
+    // decode errors are reported in the current iteration
+    let mut machine = DnaMachine::new(DnaRope::from_bases("IICIIC").unwrap());
+    machine.run_for(1);
+    machine.dna = DnaRope::from_bases("IIIPIP").unwrap();
+    let mut out = Vec::new();
+    Debugger::new(machine).next(&mut out).unwrap();
+    assert_eq!("next: DNA ran out while decoding a pattern at offset 6 in iteration 1\n", String::from_utf8(out).unwrap());
+}
+
+#[test]
+fn dna_command_test() {
+    let mut debugger = Debugger::new(DnaMachine::new(DnaRope::from_bases("ICFP").unwrap()));
+    let mut out = Vec::new();
+    debugger.command("dna 1", &mut out).unwrap();
+    debugger.command("dna 18446744073709551615", &mut out).unwrap();
+    assert_eq!("       1  CFP\n       4  \n", String::from_utf8(out).unwrap());
+}
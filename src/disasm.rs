@@ -0,0 +1,170 @@
+//! Static disassembly of DNA.
+//!
+//! The DNA is decoded one pattern and template at a time from the start,
+//! without applying any of them, which gives a listing like:
+//!
+//! ```text
+//!        0  (?"IFPP")F -> \0P
+//!       28  !0(!4536645(!800)!2971964) -> \0\1IIIIIIIIIIIIIIIIIIIIIIIP
+//!             nat IIIIIIIIIIIIIIIIIIIIIIIP = 0
+//!             rna PIPIIPC AddColor(White)
+//! ```
+//!
+//! Runs of constant bases that look like naturals (`I` and `C` bits
+//! terminated by `P`) are decoded alongside the instruction.  Decoding stops
+//! at the end of the DNA or at the first instruction that cannot be decoded.
+
+use std::io;
+use std::io::prelude::*;
+use xi_rope::tree::DnaRope;
+use dna::{self, Instruction, PItem, TItem};
+use rna::RnaCommand;
+
+/// Why a disassembly stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// All of the DNA was decoded.
+    End,
+    /// The instruction at the stopping offset could not be decoded.
+    Undecodable,
+    /// The requested number of instructions was reached.
+    Limit
+}
+
+/// A decoded instruction and its offset in the DNA.
+pub struct Entry {
+    pub offset: usize,
+    pub instruction: Instruction
+}
+
+pub struct Listing {
+    pub entries: Vec<Entry>,
+    /// Offset of the first base that was not disassembled.
+    pub offset: usize,
+    pub stop: Stop
+}
+
+/// Disassembles `dna` from the start, decoding at most `limit` instructions.
+pub fn disassemble(dna: &DnaRope, limit: Option<usize>) -> Listing {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    let stop = loop {
+        if offset >= dna.len() {
+            break Stop::End;
+        }
+        if limit == Some(entries.len()) {
+            break Stop::Limit;
+        }
+        match dna::decode(&dna.slice(offset, dna.len())) {
+            Ok(instruction) => {
+                let length = instruction.length;
+                entries.push(Entry { offset, instruction });
+                offset += length;
+            },
+            Err(_) => break Stop::Undecodable
+        }
+    };
+    Listing { entries, offset, stop }
+}
+
+/// Finds the naturals in a run of constant bases: one or more `I` (0) and
+/// `C` (1) bits, least significant first, terminated by `P`.  Runs too long
+/// to fit in a `usize` are skipped.
+pub fn naturals(bases: &str) -> Vec<(&str, usize)> {
+    let mut found = Vec::new();
+    let mut start = 0;
+    for (i, c) in bases.char_indices() {
+        match c {
+            'I' | 'C' => continue,
+            'P' if i > start => {
+                let digits = &bases[start..i];
+                let value = digits.chars().rev().try_fold(0usize, |n, d| {
+                    n.checked_mul(2).and_then(|n| n.checked_add(if d == 'C' { 1 } else { 0 }))
+                });
+                if let Some(value) = value {
+                    found.push((&bases[start..i + 1], value));
+                }
+            },
+            _ => {}
+        }
+        start = i + 1;
+    }
+    found
+}
+
+/// The runs of consecutive constant bases in an instruction.
+fn constant_runs(instruction: &Instruction) -> Vec<String> {
+    let mut runs = vec![String::new()];
+    let pattern = instruction.pattern.iter().map(|item| match *item {
+        PItem::Base(c) => Some(c),
+        _ => None
+    });
+    let template = instruction.template.iter().map(|item| match *item {
+        TItem::Base(c) => Some(c),
+        _ => None
+    });
+    for base in pattern.chain(Some(None)).chain(template) {
+        match base {
+            Some(c) => runs.last_mut().unwrap().push(c),
+            None if runs.last().unwrap().is_empty() => {},
+            None => runs.push(String::new())
+        }
+    }
+    runs
+}
+
+fn write_rna<W: Write>(r: &RnaCommand, w: &mut W) -> io::Result<()> {
+    match *r {
+        RnaCommand::Unknown(_) => writeln!(w, "            rna {}", r),
+        _ => writeln!(w, "            rna {} {:?}", r, r)
+    }
+}
+
+/// Writes `listing` in the format shown in the module documentation,
+/// followed by a line saying why disassembly stopped.
+pub fn write<W: Write>(listing: &Listing, dna: &DnaRope, w: &mut W) -> io::Result<()> {
+    for entry in &listing.entries {
+        let instruction = &entry.instruction;
+        writeln!(w, "{:8}  {} -> {}", entry.offset,
+            dna::pattern_to_string(&instruction.pattern), dna::template_to_string(&instruction.template))?;
+        for run in constant_runs(instruction) {
+            for (bases, n) in naturals(&run) {
+                writeln!(w, "            nat {} = {}", bases, n)?;
+            }
+        }
+        for r in &instruction.rna {
+            write_rna(r, w)?;
+        }
+    }
+    match listing.stop {
+        Stop::End => writeln!(w, "end of DNA at offset {}", listing.offset),
+        Stop::Limit => writeln!(w, "stopped after {} instructions at offset {}", listing.entries.len(), listing.offset),
+        Stop::Undecodable => {
+            let rest = dna.slice(listing.offset, dna.len());
+            writeln!(w, "undecodable instruction at offset {}: {}", listing.offset, dna::dna_to_string(&rest))
+        }
+    }
+}
+
+#[test]
+fn disassemble_test() {
+    let dna = DnaRope::from_bases("IIPIFFCPICICIICPIICIPPPICIICIIIPIIIIIPIIPIPIICCPIICIICCCFICIICIIF").unwrap();
+    let listing = disassemble(&dna, None);
+    assert_eq!(Stop::Undecodable, listing.stop);
+    assert_eq!(2, listing.entries.len());
+    assert_eq!(0, listing.entries[0].offset);
+    assert_eq!(28, listing.entries[1].offset);
+    let second = &listing.entries[1].instruction;
+    assert_eq!(vec![RnaCommand::Move], second.rna);
+    assert_eq!(vec![PItem::Open, PItem::Skip(12), PItem::Close], second.pattern);
+    assert_eq!(vec![TItem::Base('I'), TItem::Base('I'), TItem::Base('C'), TItem::Base('P')], second.template);
+    assert_eq!(62, listing.offset);
+    assert_eq!(Stop::Limit, disassemble(&dna, Some(1)).stop);
+    assert_eq!(Stop::End, disassemble(&dna.slice(0, 28), None).stop);
+}
+
+#[test]
+fn naturals_test() {
+    assert_eq!(vec![("IICP", 4), ("CP", 1)], naturals("FIICPPCPF"));
+    assert!(naturals("PIIF").is_empty());
+}
@@ -1,75 +1,18 @@
-use std::str::Chars;
-use xi_rope::{Rope, ChunkIter};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use xi_rope::tree::{DnaRope, DnaBuilder, BaseCursor};
+use rna::RnaCommand;
+use calltrace::{CallTrace, Event};
+use gene::Gene;
+use profile::Profile;
+use history::Record;
+use prefix;
 
-struct RopeCharIter<'a> {
-    chunk_iter: ChunkIter<'a>,
-    char_iter: Option<Chars<'a>>,
-    buf: Vec<char>,
-    buf_index: usize,
-    index: usize
-}
-
-impl<'a> Iterator for RopeCharIter<'a> {
-    type Item = char;
-
-    fn next(&mut self) -> Option<char> {
-        self.buf_index = 0;
-        self.index = self.index + 1;
-        if self.buf.is_empty() {
-            self.next_inner()
-        } else {
-            Some(self.buf.remove(0))
-        }
-    }
-}
-
-impl<'a> RopeCharIter<'a> {
-    fn next_inner(&mut self) -> Option<char> {
-        let next_char_iter = match self.char_iter {
-                Some(ref mut chars) => match chars.next() {
-                    Some(c) => {  
-                        return Some(c)
-                    },
-                    None => None
-                },
-                None => match self.chunk_iter.next() {
-                    Some(next_chunk) => Some(next_chunk.chars()), 
-                    None => return None
-                }
-            };
-            self.char_iter = next_char_iter;
-            self.next_inner()
-    }
-    
-    fn peek(&mut self) -> Option<&char> {
-        let ret = if self.buf_index < self.buf.len() {
-            Some(&self.buf[self.buf_index])
-        } else {
-            match self.next_inner() {
-                Some(x) => {
-                    self.buf.push(x);
-                    Some(&self.buf[self.buf_index])
-                }
-                None => return None,
-            }
-        };
-
-        self.buf_index += 1;
-        ret
-    }
-}
-
-fn rope_char_iter(rope: &Rope) -> RopeCharIter {
-    RopeCharIter {
-        chunk_iter: rope.iter_chunks(),
-        char_iter: None,
-        buf: Vec::new(),
-        buf_index: 0,
-        index: 0
-    }    
-}
-
-enum PItem {
+/// An item of a decoded pattern.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PItem {
     Base(char),
     Skip(usize),
     Search(String),
@@ -77,364 +20,823 @@ enum PItem {
     Close
 }
 
-enum TItem {
+/// An item of a decoded template.  `Reference(n, l)` inserts capture `n`
+/// protected `l` times, and `Length(n)` inserts the length of capture `n`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TItem {
     Base(char),
     Reference(usize, usize),
     Length(usize)
 }
 
-fn pattern_to_string(pat: &Vec<PItem>) -> String {
-    pat.iter().map(|item| match item {
-        &PItem::Base(c) => format!("{}", c),
-        &PItem::Skip(n) => format!("!{}", n),
-        &PItem::Search(ref s) => format!("?\"{}\"", s),
-        &PItem::Open => "(".to_string(),
-        &PItem::Close => ")".to_string()
+/// Formats a pattern in the notation of the task description, e.g.
+/// `(?"IFPP")F`.
+pub fn pattern_to_string(pat: &[PItem]) -> String {
+    pat.iter().map(|item| match *item {
+        PItem::Base(c) => format!("{}", c),
+        PItem::Skip(n) => format!("!{}", n),
+        PItem::Search(ref s) => format!("?\"{}\"", s),
+        PItem::Open => "(".to_string(),
+        PItem::Close => ")".to_string()
     }).collect::<String>()
 }
 
-fn template_to_string(templ: &Vec<TItem>) -> String {
-    templ.iter().map(|item| match item {
-        &TItem::Base(c) => format!("{}", c),
-        &TItem::Reference(n, l) => if l == 0 {
+/// Formats a template in the notation of the task description, e.g. `\0P`.
+pub fn template_to_string(templ: &[TItem]) -> String {
+    templ.iter().map(|item| match *item {
+        TItem::Base(c) => format!("{}", c),
+        TItem::Reference(n, l) => if l == 0 {
             format!("\\{}", n)
         } else {
             format!("\\{}({})", n, l)
         },
-        &TItem::Length(n) => format!("|{}|", n)
+        TItem::Length(n) => format!("|{}|", n)
     }).collect::<String>()
 }
 
-fn nat(chars: &mut RopeCharIter) -> Option<usize> {
-    match chars.next() {
-        Some('P') => Some(0usize),
-        Some('I') | Some('F') => nat(chars).map(|n| 2*n),
-        Some('C') => nat(chars).map(|n| 2*n + 1),
-        _ => None,
+/// The part of an instruction being decoded when the DNA ran out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Nat,
+    Consts,
+    Pattern,
+    Template
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Stage::Nat => "nat",
+            Stage::Consts => "constant",
+            Stage::Pattern => "pattern",
+            Stage::Template => "template"
+        })
     }
 }
 
-fn consts(iter: &mut RopeCharIter) -> String {
+/// Why an instruction could not be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnaErrorKind {
+    /// The DNA ran out in the middle of the instruction.
+    EndOfDna,
+    /// A nat is too large for a `usize`.
+    Overflow
+}
+
+/// An instruction that could not be decoded.  `offset` counts bases from
+/// the front of the DNA at the start of the iteration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnaError {
+    pub kind: DnaErrorKind,
+    pub stage: Stage,
+    pub offset: usize,
+    pub iteration: usize
+}
+
+impl fmt::Display for DnaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DnaErrorKind::EndOfDna => write!(f, "DNA ran out while decoding a {}", self.stage)?,
+            DnaErrorKind::Overflow => write!(f, "{} does not fit in {} bits", self.stage, usize::BITS)?
+        }
+        write!(f, " at offset {} in iteration {}", self.offset, self.iteration)
+    }
+}
+
+impl Error for DnaError {}
+
+// the iteration is filled in by `DnaMachine::step`
+fn ran_out(stage: Stage, bases: &BaseCursor) -> DnaError {
+    DnaError { kind: DnaErrorKind::EndOfDna, stage, offset: bases.pos(), iteration: 0 }
+}
+
+fn nat(bases: &mut BaseCursor) -> Result<usize, DnaError> {
+    let mut n = 0usize;
+    // the weight of the next base; zeros past the top bit are harmless
+    let mut bit = 0;
+    loop {
+        match bases.next() {
+            Some(b'P') => return Ok(n),
+            Some(b'I') | Some(b'F') => {},
+            Some(b'C') if bit < usize::BITS => n |= 1 << bit,
+            Some(b'C') => {
+                let offset = bases.pos() - 1;
+                return Err(DnaError { kind: DnaErrorKind::Overflow, stage: Stage::Nat, offset, iteration: 0 })
+            },
+            _ => return Err(ran_out(Stage::Nat, bases))
+        }
+        bit = bit.saturating_add(1);
+    }
+}
+
+#[test]
+fn nat_test() {
+    let long_zero = "I".repeat(1000000) + "P";
+    let cases = [("P", Ok(0)), ("ICFCP", Ok(10)), (long_zero.as_str(), Ok(0)), ("ICI", Err(DnaErrorKind::EndOfDna))];
+    for &(bases, expected) in cases.iter() {
+        let rope = DnaRope::from_bases(bases).unwrap();
+        assert_eq!(expected, nat(&mut BaseCursor::new(&rope, 0)).map_err(|e| e.kind));
+    }
+    let max = "C".repeat(usize::BITS as usize);
+    let rope = DnaRope::from_bases(&(max.clone() + "P")).unwrap();
+    assert_eq!(Ok(usize::MAX), nat(&mut BaseCursor::new(&rope, 0)));
+    let rope = DnaRope::from_bases(&(max + "CP")).unwrap();
+    let error = nat(&mut BaseCursor::new(&rope, 0)).unwrap_err();
+    assert_eq!((DnaErrorKind::Overflow, usize::BITS as usize), (error.kind, error.offset));
+}
+
+fn consts(bases: &mut BaseCursor) -> Result<String, DnaError> {
     let mut ret = String::from("");
     loop {
-        match iter.peek() {
-            Some(&'C') => { ret.push('I'); iter.next(); },
-            Some(&'F') => { ret.push('C'); iter.next(); },
-            Some(&'P') => { ret.push('F'); iter.next(); },
-            Some(&'I') => {
-                match iter.peek() {
-                    Some(&'C') => {
-                        iter.next();
-                        iter.next();
-                        ret.push('P')
-                    },
-                    _ => return ret.to_string()
+        match bases.peek() {
+            Some(b'C') => { ret.push('I'); bases.next(); },
+            Some(b'F') => { ret.push('C'); bases.next(); },
+            Some(b'P') => { ret.push('F'); bases.next(); },
+            Some(b'I') => {
+                bases.next();
+                if bases.peek() == Some(b'C') {
+                    bases.next();
+                    ret.push('P')
+                } else {
+                    bases.prev();
+                    return Ok(ret)
                 }
             },
-            _ => return ret.to_string()
+            _ => return Err(ran_out(Stage::Consts, bases))
         };
-        
     }
 }
 
 #[test]
 fn consts_test() {
     let input = "CPICCFPICICFCPPIIC";
-    let rope = Rope::from(input);
-    let mut iter = rope_char_iter(&rope);
-    assert_eq!("IFPICFPPCIFF", consts(&mut iter));    
-    assert_eq!("IIC", &String::from(rope.clone().slice(iter.index, rope.len())));    
+    let rope = DnaRope::from_bases(input).unwrap();
+    let mut bases = BaseCursor::new(&rope, 0);
+    assert_eq!(Ok(String::from("IFPICFPPCIFF")), consts(&mut bases));
+    assert_eq!("IIC", &String::from(rope.slice(bases.pos(), rope.len())));
+    let rope = DnaRope::from_bases("CPIC").unwrap();
+    let error = consts(&mut BaseCursor::new(&rope, 0)).unwrap_err();
+    assert_eq!((Stage::Consts, 4), (error.stage, error.offset));
 }
 
-fn pattern(chars: &mut RopeCharIter) -> Option<(Vec<String>, Vec<PItem>)> {
+fn rna_command(bases: &mut BaseCursor, stage: Stage) -> Result<RnaCommand, DnaError> {
+    let command = bases.take(7).map(|b| b as char).collect::<String>();
+    if command.len() < 7 {
+        return Err(ran_out(stage, bases));
+    }
+    Ok(RnaCommand::from(command.as_str()))
+}
+
+fn pattern(bases: &mut BaseCursor) -> Result<(Vec<RnaCommand>, Vec<PItem>), DnaError> {
     let mut rna = Vec::new();
     let mut p = Vec::new();
     let mut lvl = 0;
     loop {
-        match chars.next() {
-            Some('C') => p.push(PItem::Base('I')),
-            Some('F') => p.push(PItem::Base('C')),
-            Some('P') => p.push(PItem::Base('F')),
-            Some('I') => match chars.next() {
-                Some('C') => p.push(PItem::Base('P')),
-                Some('P') => match nat(chars) {
-                    Some(n) => p.push(PItem::Skip(n)),     
-                    None => return None
-                },
-                Some('F') => {
-                    chars.next(); // three bases consumed!
-                    let s = consts(chars);
-                    p.push(PItem::Search(s));
+        match bases.next() {
+            Some(b'C') => p.push(PItem::Base('I')),
+            Some(b'F') => p.push(PItem::Base('C')),
+            Some(b'P') => p.push(PItem::Base('F')),
+            Some(b'I') => match bases.next() {
+                Some(b'C') => p.push(PItem::Base('P')),
+                Some(b'P') => p.push(PItem::Skip(nat(bases)?)),
+                Some(b'F') => {
+                    // three bases consumed!
+                    if bases.next().is_none() {
+                        return Err(ran_out(Stage::Pattern, bases));
+                    }
+                    p.push(PItem::Search(consts(bases)?));
                 },
-                Some('I') => match chars.next() {
-                    Some('P') => {
-                        lvl = lvl + 1;
+                Some(b'I') => match bases.next() {
+                    Some(b'P') => {
+                        lvl += 1;
                         p.push(PItem::Open);
                     },
-                    Some('C') | Some('F') => {
+                    Some(b'C') | Some(b'F') => {
                         if lvl == 0 {
-                            return Some((rna, p)); 
+                            return Ok((rna, p));
                         } else {
-                            lvl = lvl - 1;
+                            lvl -= 1;
                             p.push(PItem::Close); 
                         }
                     },
-                    Some('I') => rna.push(chars.take(7).collect::<String>()),
-                    _ => return None
+                    Some(b'I') => rna.push(rna_command(bases, Stage::Pattern)?),
+                    _ => return Err(ran_out(Stage::Pattern, bases))
                 },
-                _ => return None
+                _ => return Err(ran_out(Stage::Pattern, bases))
             },        
-            _ => return None
+            _ => return Err(ran_out(Stage::Pattern, bases))
         }
     }
 }
 
-fn template(chars: &mut RopeCharIter) -> Option<(Vec<String>, Vec<TItem>)> {
+fn template(bases: &mut BaseCursor) -> Result<(Vec<RnaCommand>, Vec<TItem>), DnaError> {
     let mut rna = Vec::new();
     let mut t = Vec::new();
     loop {
-        match chars.next() {
-            Some('C') => t.push(TItem::Base('I')),
-            Some('F') => t.push(TItem::Base('C')),
-            Some('P') => t.push(TItem::Base('F')),
-            Some('I') => match chars.next() {
-                Some('C') => t.push(TItem::Base('P')),
-                Some('F') | Some('P') => match nat(chars) {
-                    Some(l) => match nat(chars) {
-                        Some(n) => t.push(TItem::Reference(n, l)),
-                        None => return None
-                    },
-                    None => return None
+        match bases.next() {
+            Some(b'C') => t.push(TItem::Base('I')),
+            Some(b'F') => t.push(TItem::Base('C')),
+            Some(b'P') => t.push(TItem::Base('F')),
+            Some(b'I') => match bases.next() {
+                Some(b'C') => t.push(TItem::Base('P')),
+                Some(b'F') | Some(b'P') => {
+                    let l = nat(bases)?;
+                    let n = nat(bases)?;
+                    t.push(TItem::Reference(n, l));
                 },
-                Some('I') => match chars.next() {
-                    Some('C') | Some('F') => return Some((rna, t)),
-                    Some('P') => match nat(chars) {
-                        Some(n) => t.push(TItem::Length(n)),
-                        None => return None
-                    },
-                    Some('I') => rna.push(chars.by_ref().take(7).collect::<String>()),
-                    _ => return None
+                Some(b'I') => match bases.next() {
+                    Some(b'C') | Some(b'F') => return Ok((rna, t)),
+                    Some(b'P') => t.push(TItem::Length(nat(bases)?)),
+                    Some(b'I') => rna.push(rna_command(bases, Stage::Template)?),
+                    _ => return Err(ran_out(Stage::Template, bases))
                 },
-                _ => return None
+                _ => return Err(ran_out(Stage::Template, bases))
             },
-            _ => return None
+            _ => return Err(ran_out(Stage::Template, bases))
         }
     }
 }
 
-fn match_replace(p: Vec<PItem>, t: &Vec<TItem>, dna: Rope, logging: bool) -> Rope {
-    let mut i = 0usize;
+/// A pattern and template decoded from the front of some DNA.
+pub struct Instruction {
+    pub pattern: Vec<PItem>,
+    pub template: Vec<TItem>,
+    /// RNA emitted while decoding, in order.
+    pub rna: Vec<RnaCommand>,
+    /// Number of bases taken up by the pattern and template.
+    pub length: usize
+}
+
+/// Decodes the pattern and template at the front of `dna` without applying
+/// them.  The `iteration` of an error is always 0.
+pub fn decode(dna: &DnaRope) -> Result<Instruction, DnaError> {
+    let mut bases = BaseCursor::new(dna, 0);
+    let (mut rna, pattern) = pattern(&mut bases)?;
+    let (rna2, template) = template(&mut bases)?;
+    rna.extend(rna2);
+    Ok(Instruction { pattern, template, rna, length: bases.pos() })
+}
+
+/// Matches `p` against `dna` starting at offset `start`, returning the
+/// number of bases matched and the captured environment.
+pub fn matches(p: &[PItem], dna: &DnaRope, start: usize) -> Option<(usize, Vec<DnaRope>)> {
+    match_pattern(p, dna, start, None)
+}
+
+fn match_pattern(p: &[PItem], dna: &DnaRope, start: usize, mut profile: Option<&mut Profile>) -> Option<(usize, Vec<DnaRope>)> {
+    let mut i = start;
     let mut e = Vec::new();
     let mut c = Vec::new();
-    let mut failed = false;
     for item in p {
-        match item {
-            PItem::Base(c) => { 
-                if dna.byte_at(i) == c as u8 {
-                    i = i + 1
+        if let Some(ref mut profile) = profile {
+            profile.pattern_items.count(item);
+        }
+        match *item {
+            PItem::Base(b) => {
+                if i < dna.len() && dna.byte_at(i) == b as u8 {
+                    i += 1
                 } else {
-                    failed = true
+                    return None
                 }
             },
             PItem::Skip(n) => {
-                i = i + n;
-                if i > dna.len() {
-                    failed = true
+                i = match i.checked_add(n) {
+                    Some(i) if i <= dna.len() => i,
+                    _ => return None
                 }
             },
             PItem::Search(ref s) => {
-                match search(i, s, &dna) {
+                let found = dna.find(s, i).map(|n| n + s.len());
+                if let Some(ref mut profile) = profile {
+                    profile.searched += found.unwrap_or(dna.len()) - i;
+                }
+                match found {
                     Some(n) => i = n,
-                    None => failed = true
+                    None => return None
                 }
             },
             PItem::Open => c.push(i),
             PItem::Close => match c.pop()  {
-                Some(c0) => e.push(dna.clone().slice(c0, i)),
-                None => failed = true
-            }
-        }
-        if failed {
-            if logging { println!("failed match") }
-            return dna 
-        }
-    }
-    if logging {
-        println!("succesful match of length {}", i);
-        for (i, captured) in e.iter().enumerate() {
-           println!("e[{}] = {}", i, dna_to_string(&captured));
-        }
-    }
-    let dna_len = dna.len();
-    replace(t, e, dna.slice(i, dna_len))
-}
-
-fn search(i: usize, s: &str, dna: &Rope) -> Option<usize> {
-    let mut n = 0;
-    let rest_dna = dna.clone().slice(i, dna.len());
-    let mut iter = rope_char_iter(&rest_dna);
-    loop {
-        let mut s_iter = s.chars();
-        loop {
-            let schar = s_iter.next();
-            let dnachar = iter.peek();
-            match (schar, dnachar) {
-                (None, _) => return Some(n + i + s.len()),
-                (Some(sc), Some(dc)) if sc == *dc => continue,
-                _ => {
-                    break;
-                }  
+                Some(c0) => e.push(dna.slice(c0, i)),
+                None => return None
             }
         }
-        n = n + 1;
-        match iter.next() {
-            None => return None,
-            Some(_) => continue
-        }
     }
+    Some((i - start, e))
 }
 
-fn env_get(e: &Vec<Rope>, i: usize) -> Rope  {
+fn env_get(e: &[DnaRope], i: usize) -> DnaRope  {
     if i >= e.len() {
-        Rope::from("")
+        DnaRope::default()
     } else {
         e[i].clone()
     } 
 }
 
-fn replace(t: &Vec<TItem>, e: Vec<Rope>, dna: Rope) -> Rope {
-    let mut ret = Rope::from("");
-    let mut bases = String::new();
+fn replace(t: &[TItem], e: Vec<DnaRope>, dna: DnaRope, mut profile: Option<&mut Profile>) -> DnaRope {
+    let mut ret = DnaBuilder::new();
     for item in t {
-        match item {
-            &TItem::Base(c) => bases.push_str(&c.to_string()),
-            &TItem::Reference(n, l) => {
-                if bases.len() > 0 {
-                    ret.push_str(&bases);
-                    bases = String::new();
+        match *item {
+            TItem::Base(c) => ret.push_base(c as u8),
+            TItem::Reference(n, l) => {
+                let mut quoted = 0;
+                let captured = env_get(&e, n);
+                let captured_len = captured.len();
+                ret.push(protect(l, captured, &mut quoted));
+                if let Some(ref mut profile) = profile {
+                    *profile.levels.entry(l).or_insert(0) += 1;
+                    if l > 0 {
+                        profile.protected += captured_len;
+                    }
+                    profile.quoted += quoted;
                 }
-                ret.push(protect(l, env_get(&e, n)))
             },
-            &TItem::Length(n) => {
-                if bases.len() > 0 {
-                    ret.push_str(&bases);
-                    bases = String::new();
-                }
-                ret.push(asnat(env_get(&e, n).len()))
-            }
+            TItem::Length(n) => ret.push_str(&prefix::nat(env_get(&e, n).len()))
+        }
+        if let Some(ref mut profile) = profile {
+            profile.template_items.count(item);
         }
-    }
-    if bases.len() > 0 {
-        ret.push_str(&bases);
     }
     ret.push(dna);
-    ret
+    ret.build()
 }
 
-fn protect(l: usize, d: Rope) -> Rope {
+/// Quotes `d` `l` times, adding the number of bases quoted to `quoted`.
+fn protect(l: usize, d: DnaRope, quoted: &mut usize) -> DnaRope {
     if l == 0 {
         d
     } else {
-        protect(l - 1, quote(d))
+        *quoted += d.len();
+        protect(l - 1, quote(d), quoted)
     }
 }
 
-fn quote(d: Rope) -> Rope {
-    let mut s = String::new();
-    for c in rope_char_iter(&d) {
+fn quote(d: DnaRope) -> DnaRope {
+    let mut ret = DnaBuilder::new();
+    for b in BaseCursor::new(&d, 0) {
+        match b {
+            b'I' => ret.push_base(b'C'),
+            b'C' => ret.push_base(b'F'),
+            b'F' => ret.push_base(b'P'),
+            _ => {
+                ret.push_base(b'I');
+                ret.push_base(b'C');
+            }
+        }
+    }
+    ret.build()
+}
+
+pub fn dna_to_string(dna: &DnaRope) -> String {
+   let mut s = BaseCursor::new(dna, 0).take(10).map(|b| b as char).collect::<String>();
+   if dna.len() > 10 {
+       s += "...";
+   }
+   s = s + " (" + &dna.len().to_string() + " bases)";
+   s
+}
+
+/// Quotes `s` as a JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
         match c {
-            'I' => s = s + "C",
-            'C' => s = s + "F",
-            'F' => s = s + "P",
-            'P' => s = s + "IC",
-            _ => return Rope::from(s)
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            _ => out.push(c)
         }
     }
-    Rope::from(s)
+    out.push('"');
+    out
 }
 
-fn asnat(mut n: usize) -> Rope {
-    let mut s = String::new();
-    loop {
-        if n == 0 {
-            s = s + "P";
-            return Rope::from(s);
+/// Formats one iteration of the JSON trace, e.g.
+///
+/// ```text
+/// {"iteration":0,"dna_length":7523088,"pattern":"(?\"IFPP\")F","template":"\\0P","matched":true,"match_length":13704,"captures":[13703],"rna":[]}
+/// ```
+///
+/// `match_length` is `null` and `captures` is empty when the match fails.
+fn trace_line(iteration: usize, dna_length: usize, p: &[PItem], t: &[TItem],
+              matched: &Option<(usize, Vec<DnaRope>)>, rna: &[RnaCommand]) -> String {
+    let (match_length, captures) = match *matched {
+        Some((i, ref e)) => (i.to_string(), e.iter().map(|c| c.len().to_string()).collect::<Vec<_>>()),
+        None => (String::from("null"), Vec::new())
+    };
+    let rna = rna.iter().map(|r| json_string(&r.to_string())).collect::<Vec<_>>();
+    format!("{{\"iteration\":{},\"dna_length\":{},\"pattern\":{},\"template\":{},\"matched\":{},\"match_length\":{},\"captures\":[{}],\"rna\":[{}]}}",
+        iteration, dna_length, json_string(&pattern_to_string(p)), json_string(&template_to_string(t)),
+        matched.is_some(), match_length, captures.join(","), rna.join(","))
+}
+
+/// Limits on a run of a `DnaMachine`, each unlimited when `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Iterations to run, counting those before a snapshot was taken.
+    pub iterations: Option<usize>,
+    /// Bases the DNA may grow to.
+    pub dna_length: Option<usize>,
+    /// RNA commands after which the run stops, counting those before a
+    /// snapshot.  The step that reaches the limit can emit a few more.
+    pub rna: Option<usize>,
+    /// Time from the first step of the machine, or the first step after
+    /// it was restored.
+    pub time: Option<Duration>
+}
+
+/// The limit that stopped a run, with the value it was set to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Iterations(usize),
+    DnaLength(usize),
+    Rna(usize),
+    Time(Duration)
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Limit::Iterations(n) => write!(f, "reached the limit of {} iterations", n),
+            Limit::DnaLength(n) => write!(f, "DNA grew past the limit of {} bases", n),
+            Limit::Rna(n) => write!(f, "RNA reached the limit of {} commands", n),
+            Limit::Time(t) => write!(f, "ran past the time limit of {:.1}s", t.as_secs_f64())
         }
-        s = s + (match n % 2 { 0 => "I", _ => "C" });
-        n = n / 2;
     }
 }
 
-pub fn dna_to_string(dna: &Rope) -> String {
-   let mut s = rope_char_iter(dna).take(10).collect::<String>();
-   if dna.len() > 10 {
-       s = s + "...";
-   }
-   s = s + " (" + &dna.len().to_string() + " bases)";
-   s
+/// The state of the DNA interpreter between iterations.
+///
+/// Each call to `step` decodes one pattern and template from the front of
+/// the DNA, applies them, and appends any RNA emitted along the way.  The
+/// machine can be inspected between steps and resumed at any point.
+pub struct DnaMachine {
+    pub dna: DnaRope,
+    pub rna: Vec<RnaCommand>,
+    /// Number of iterations completed so far.
+    pub iteration: usize,
+    pub logging: bool,
+    /// Makes `run` print the iteration every 10000 iterations.
+    pub progress: bool,
+    /// Prints the gene calls followed in `calls` as they start and finish.
+    pub tracing: bool,
+    /// If set, follows the gene calls made through RNA markers.
+    pub calls: Option<CallTrace>,
+    /// If set, one JSON object per iteration is written here; see
+    /// `trace_line` for the fields.
+    pub json_trace: Option<Box<dyn Write>>,
+    /// Checked before each step; raising them lets a stopped run continue.
+    pub limits: Limits,
+    /// If set, counts what each step does and times its phases.
+    pub profile: Option<Profile>,
+    finished: bool,
+    error: Option<DnaError>,
+    limit: Option<Limit>,
+    started: Option<Instant>
 }
 
-pub fn execute(mut dna: Rope, logging: bool, tracing: bool) -> Vec<String> {
-    let mut rna = Vec::new();
-    let mut iteration = -1;
-    let mut indentation = String::from("");
-    loop {
-        iteration = iteration + 1;
-        if iteration % 10000 == 0 {
-            println!("iteration = {}", iteration);
+impl DnaMachine {
+    pub fn new(dna: DnaRope) -> DnaMachine {
+        DnaMachine {
+            dna,
+            rna: Vec::new(),
+            iteration: 0,
+            logging: false,
+            progress: false,
+            tracing: false,
+            calls: None,
+            json_trace: None,
+            limits: Limits::default(),
+            profile: None,
+            finished: false,
+            error: None,
+            limit: None,
+            started: None
         }
-        if logging {
-            println!("");
-            println!("iteration = {}", iteration);
-            println!("dna = {}", dna_to_string(&dna));
-        }
-        let (p, t, index, new_rna) = {
-            let mut new_rna = Vec::new();
-            let mut chars = rope_char_iter(&dna);
-            let (rna2, p) = match pattern(&mut chars) {
-                None => return rna,
-                Some((rna2, p)) => (rna2, p)
-            };
-            if logging {
-                println!("pattern  {}", pattern_to_string(&p));
+    }
+
+    /// Returns true once the DNA can no longer be decoded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// The reason the machine finished, if the DNA ran out in the middle of
+    /// an instruction rather than between two.
+    pub fn error(&self) -> Option<&DnaError> {
+        self.error.as_ref()
+    }
+
+    /// Puts the machine back at the start of `iteration`, with `dna` and
+    /// the first `rna_count` RNA commands, ready to run again.
+    pub fn restore(&mut self, iteration: usize, dna: DnaRope, rna_count: usize) {
+        self.iteration = iteration;
+        self.dna = dna;
+        self.rna.truncate(rna_count);
+        self.finished = false;
+        self.error = None;
+        self.limit = None;
+        self.started = None;
+    }
+
+    /// The limit that stopped the last step, if any.
+    pub fn limit(&self) -> Option<Limit> {
+        self.limit
+    }
+
+    fn finish(&mut self, error: Option<DnaError>) {
+        self.finished = true;
+        self.error = error;
+        self.flush_trace();
+    }
+
+    fn flush_trace(&mut self) {
+        if let Some(ref mut w) = self.json_trace {
+            let _ = w.flush();
+        }
+    }
+
+    fn exceeded_limit(&self) -> Option<Limit> {
+        let limits = self.limits;
+        if let Some(n) = limits.iterations {
+            if self.iteration >= n {
+                return Some(Limit::Iterations(n));
             }
-            for r in rna2 {
-                new_rna.push(r);
+        }
+        if let Some(n) = limits.dna_length {
+            if self.dna.len() > n {
+                return Some(Limit::DnaLength(n));
             }
-            let (rna3, t) = match template(&mut chars) {
-                None => return rna,
-                Some((rna3, t)) => (rna3, t)
-            };
-            if logging {
-                println!("template {}", template_to_string(&t));
+        }
+        if let Some(n) = limits.rna {
+            if self.rna.len() >= n {
+                return Some(Limit::Rna(n));
             }
-            for r in rna3 {
-                new_rna.push(r);
+        }
+        if let Some(t) = limits.time {
+            if self.started.is_some_and(|started| started.elapsed() > t) {
+                return Some(Limit::Time(t));
             }
-            if logging {
-                println!("len(pattern + template) = {}", chars.index);
+        }
+        None
+    }
+
+    fn write_trace(&mut self, line: &str) {
+        let result = match self.json_trace {
+            Some(ref mut w) => writeln!(w, "{}", line),
+            None => Ok(())
+        };
+        if let Err(e) = result {
+            eprintln!("cannot write JSON trace: {}", e);
+            self.json_trace = None;
+        }
+    }
+
+    /// Runs a single iteration.  Returns false, and marks the machine as
+    /// finished, if the DNA is empty or no pattern and template could be
+    /// decoded.  Also returns false, without running anything, while one of
+    /// the `limits` is exceeded.
+    pub fn step(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+        self.started.get_or_insert_with(Instant::now);
+        self.limit = self.exceeded_limit();
+        if self.limit.is_some() {
+            self.flush_trace();
+            return false;
+        }
+        let logging = self.logging;
+        if logging {
+            println!();
+            println!("iteration = {}", self.iteration);
+            println!("dna = {}", dna_to_string(&self.dna));
+        }
+        let record = Record::of(self);
+        if let Some(ref mut profile) = self.profile {
+            profile.sample(self.iteration, record);
+        }
+        if self.dna.len() == 0 {
+            self.finish(None);
+            return false;
+        }
+        let clock = self.profile.as_ref().map(|_| Instant::now());
+        let decoded = decode(&self.dna);
+        if let (Some(profile), Some(clock)) = (self.profile.as_mut(), clock) {
+            profile.decode_time += clock.elapsed();
+        }
+        let Instruction { pattern: p, template: t, rna: new_rna, length: index } = match decoded {
+            Ok(instruction) => instruction,
+            Err(mut e) => {
+                e.iteration = self.iteration;
+                self.finish(Some(e));
+                return false;
             }
-            (p, t, chars.index, new_rna)
         };
-        let dna_len = dna.len();
-        dna = match_replace(p, &t, dna.slice(index, dna_len), logging);
+        if logging {
+            println!("pattern  {}", pattern_to_string(&p));
+            println!("template {}", template_to_string(&t));
+            println!("len(pattern + template) = {}", index);
+        }
+        let dna_len = self.dna.len();
+        let clock = self.profile.as_ref().map(|_| Instant::now());
+        let matched = match_pattern(&p, &self.dna, index, self.profile.as_mut());
+        if let (Some(profile), Some(clock)) = (self.profile.as_mut(), clock) {
+            profile.iterations += 1;
+            if matched.is_some() {
+                profile.matches += 1;
+            } else {
+                profile.failed_matches += 1;
+            }
+            profile.match_time += clock.elapsed();
+        }
+        if logging {
+            match matched {
+                Some((i, ref e)) => {
+                    println!("succesful match of length {}", i);
+                    for (i, captured) in e.iter().enumerate() {
+                       println!("e[{}] = {}", i, dna_to_string(captured));
+                    }
+                },
+                None => println!("failed match")
+            }
+        }
+        if self.json_trace.is_some() {
+            let line = trace_line(self.iteration, dna_len, &p, &t, &matched, &new_rna);
+            self.write_trace(&line);
+        }
+        let clock = self.profile.as_ref().map(|_| Instant::now());
+        self.dna = match matched {
+            Some((i, e)) => replace(&t, e, self.dna.slice(index + i, dna_len), self.profile.as_mut()),
+            None => self.dna.slice(index, dna_len)
+        };
+        if let (Some(profile), Some(clock)) = (self.profile.as_mut(), clock) {
+            profile.replace_time += clock.elapsed();
+        }
         for r in new_rna {
-            if tracing && r.starts_with("C") {
-                match r.as_ref() {
-                    "CFPICFP" => {
-                        indentation.pop();
-                        println!("{:8}:{}Finished.", iteration, indentation);
+            if let Some(ref mut calls) = self.calls {
+                match calls.observe(self.iteration, &r) {
+                    Some(Event::Enter(depth, call)) if self.tracing => match call.name {
+                        Some(name) => println!("{:8}:{}Starting {} ({}).", self.iteration, " ".repeat(depth), name, call.marker),
+                        None => println!("{:8}:{}Starting {}.", self.iteration, " ".repeat(depth), call.marker)
                     },
-                    _ => {
-                        println!("{:8}:{}Starting {}.", iteration, indentation, r);
-                        indentation = indentation + " ";
-                    }
+                    Some(Event::Return(depth, call)) if self.tracing => {
+                        println!("{:8}:{}Finished {}.", self.iteration, " ".repeat(depth), call.label());
+                    },
+                    _ => {}
                 }
             }
-            rna.push(r);
+            self.rna.push(r);
         }
         if logging {
-            println!("len(rna) = {}", rna.len());
+            println!("len(rna) = {}", self.rna.len());
+        }
+        self.iteration += 1;
+        true
+    }
+
+    /// Runs at most `n` iterations, returning how many were executed.
+    pub fn run_for(&mut self, n: usize) -> usize {
+        let mut executed = 0;
+        while executed < n && self.step() {
+            executed += 1;
+        }
+        executed
+    }
+
+    /// Runs iterations until `predicate` holds after one of them, or the
+    /// DNA is exhausted.  Returns true if the predicate stopped the run.
+    pub fn run_until<F>(&mut self, mut predicate: F) -> bool
+            where F: FnMut(&DnaMachine) -> bool {
+        while self.step() {
+            if predicate(self) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Runs until the DNA is exhausted or a limit is reached, reporting
+    /// progress if `progress` is set.
+    pub fn run(&mut self) {
+        loop {
+            if self.progress && self.iteration.is_multiple_of(10000) {
+                println!("iteration = {}", self.iteration);
+            }
+            if !self.step() {
+                break;
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Runs `dna` until it is exhausted or one of `limits` is reached, and
+/// returns the RNA it produced.  With `tracing`, gene calls are printed
+/// with their names from `genes`, usually `gene::gene_table` of Endo's DNA.
+pub fn execute(dna: DnaRope, logging: bool, tracing: bool, genes: &[Gene], limits: Limits) -> Vec<RnaCommand> {
+    let mut machine = DnaMachine::new(dna);
+    machine.logging = logging;
+    machine.tracing = tracing;
+    if tracing {
+        machine.calls = Some(CallTrace::new(genes));
+    }
+    machine.limits = limits;
+    machine.run();
+    machine.rna
+}
+
+#[test]
+fn search_test() {
+    let p = [PItem::Open, PItem::Search(String::from("IFPP")), PItem::Close];
+    let (i, e) = matches(&p, &DnaRope::from_bases("IIICIFPIFPPC").unwrap(), 3).unwrap();
+    assert_eq!(8, i);
+    assert_eq!("CIFPIFPP", String::from(&e[0]));
+    assert!(matches(&p, &DnaRope::from_bases("CIFPIFP").unwrap(), 0).is_none());
+}
+
+#[test]
+fn step_test() {
+    let cases = [
+        ("IIPIPICPIICICIIFICCIFPPIICCFPC", "PICFC"),
+        ("IIPIPICPIICICIIFICCIFCCCPPIICCFPC", "PIICCFCFFPC"),
+        ("IIPIPIICPIICIICCIICFCFC", "I")
+    ];
+    for &(dna, expected) in cases.iter() {
+        let mut machine = DnaMachine::new(DnaRope::from_bases(dna).unwrap());
+        assert!(machine.step());
+        assert_eq!(expected, String::from(&machine.dna));
+        assert_eq!(1, machine.iteration);
+    }
+}
+
+#[test]
+fn trace_line_test() {
+    let p = [PItem::Open, PItem::Search(String::from("IFPP")), PItem::Close, PItem::Base('F')];
+    let t = [TItem::Reference(0, 0), TItem::Base('P')];
+    let matched = Some((5, vec![DnaRope::from_bases("IFPP").unwrap()]));
+    assert_eq!(r#"{"iteration":3,"dna_length":40,"pattern":"(?\"IFPP\")F","template":"\\0P","matched":true,"match_length":5,"captures":[4],"rna":["PIIIIIP"]}"#,
+        trace_line(3, 40, &p, &t, &matched, &[RnaCommand::Move]));
+    assert!(trace_line(0, 0, &p, &t, &None, &[]).contains(r#""matched":false,"match_length":null,"captures":[],"rna":[]"#));
+}
+
+#[test]
+fn run_test() {
+    let mut machine = DnaMachine::new(DnaRope::from_bases("IIIPIPIIPCIICIICIIIPIPIIPFIICIIC").unwrap());
+    assert!(machine.run_until(|m| m.rna.len() == 1));
+    assert_eq!(vec![RnaCommand::from("PIPIIPC")], machine.rna);
+    assert_eq!(1, machine.run_for(5));
+    assert!(machine.is_finished());
+    assert_eq!(vec![RnaCommand::from("PIPIIPC"), RnaCommand::from("PIPIIPF")], machine.rna);
+    assert_eq!(2, machine.iteration);
+    assert_eq!(None, machine.error());
+}
+
+#[test]
+fn limits_test() {
+    // each iteration emits one RNA command and consumes 16 bases
+    let dna = "IIIPIPIIPCIICIIC".repeat(10);
+    let mut machine = DnaMachine::new(DnaRope::from_bases(&dna).unwrap());
+    machine.limits.iterations = Some(3);
+    machine.run();
+    assert_eq!(Some(Limit::Iterations(3)), machine.limit());
+    assert_eq!(3, machine.iteration);
+    assert!(!machine.is_finished());
+    machine.limits = Limits { rna: Some(4), ..Limits::default() };
+    machine.run();
+    assert_eq!(Some(Limit::Rna(4)), machine.limit());
+    assert_eq!(4, machine.rna.len());
+    machine.limits = Limits { dna_length: Some(dna.len() - 100), ..Limits::default() };
+    assert!(!machine.step());
+    assert_eq!(Some(Limit::DnaLength(dna.len() - 100)), machine.limit());
+    machine.limits = Limits { time: Some(Duration::from_secs(0)), ..Limits::default() };
+    assert!(!machine.step());
+    assert_eq!(Some(Limit::Time(Duration::from_secs(0))), machine.limit());
+    machine.limits = Limits::default();
+    machine.run();
+    assert_eq!(None, machine.limit());
+    assert!(machine.is_finished());
+    assert_eq!(10, machine.rna.len());
+
+    // the clock starts again after a restore
+    let mut machine = DnaMachine::new(DnaRope::from_bases(&dna).unwrap());
+    machine.limits.time = Some(Duration::from_millis(50));
+    assert!(machine.step());
+    ::std::thread::sleep(Duration::from_millis(60));
+    assert!(!machine.step());
+    assert_eq!(Some(Limit::Time(Duration::from_millis(50))), machine.limit());
+    let dna = machine.dna.clone();
+    machine.restore(1, dna, 1);
+    assert!(machine.step());
+    assert_eq!(None, machine.limit());
+}
+
+#[test]
+fn error_test() {
+    let cases = [("IIPIPICC", Stage::Nat, 8), ("IFFCP", Stage::Consts, 5), ("IIIPIP", Stage::Pattern, 6), ("IICIPP", Stage::Nat, 6), ("IICCI", Stage::Template, 5)];
+    for &(dna, stage, offset) in cases.iter() {
+        let mut machine = DnaMachine::new(DnaRope::from_bases("IICIIC").unwrap());
+        machine.run_for(1);
+        machine.dna = DnaRope::from_bases(dna).unwrap();
+        assert!(!machine.step());
+        assert!(machine.is_finished());
+        assert_eq!(Some(&DnaError { kind: DnaErrorKind::EndOfDna, stage, offset, iteration: 1 }), machine.error());
+    }
+}
@@ -1,4 +1,8 @@
-use xi_rope::Rope;
+use xi_rope::tree::DnaRope;
+
+/// Offset in Endo's DNA of the first base after the green zone marker.
+/// Gene offsets are relative to this position.
+pub const GREEN_ZONE_START: usize = 13615;
 
 pub struct Gene {
     pub name: &'static str,
@@ -8,10 +12,10 @@ pub struct Gene {
 }
 
 fn new_gene(name: &'static str, offset: usize, length: usize) -> Gene {
-    Gene {name: name, offset: offset, length: length, code: None }
+    Gene {name, offset, length, code: None }
 }
 
-pub fn gene_table(dna: &Rope) -> Vec<Gene> {
+pub fn gene_table(dna: &DnaRope) -> Vec<Gene> {
     let mut genes = vec![
         new_gene("AAA_genePageTableNr", 0x510,  0x18 ),
         new_gene("M-class-planet", 0x2ccd88,  0x3c7f0 ),
@@ -107,9 +111,11 @@ pub fn gene_table(dna: &Rope) -> Vec<Gene> {
         new_gene("colorGreen", 0x35cd8d,  0x172 ),
     ];
     
-    for i in 0..genes.len() {
-        let mut gene = &mut genes[i];
-        let bases = dna.clone().slice(13615 + gene.offset, 13615 + gene.offset + gene.length);
+    // genes past the end of a short DNA keep no code
+    for gene in genes.iter_mut() {
+        let start = (GREEN_ZONE_START + gene.offset).min(dna.len());
+        let end = (GREEN_ZONE_START + gene.offset + gene.length).min(dna.len());
+        let bases = dna.slice(start, end);
         if bases.len() >= 10 {
             gene.code = Some(String::from(bases.slice(3, 10)))
         }
@@ -0,0 +1,124 @@
+//! Execution history of a `DnaMachine`, for going back in time.
+//!
+//! Ropes are persistent, so keeping an old DNA around only costs the nodes
+//! that later iterations replace.  The history keeps the DNA every
+//! `interval` iterations as a checkpoint, and a small record of every
+//! iteration in between.  The RNA only ever grows, so a checkpoint just
+//! remembers how much of it there was.
+//!
+//! Going back to an iteration restores the last checkpoint before it and
+//! re-executes from there, which gives the same DNA as the first time.
+
+use xi_rope::tree::DnaRope;
+use dna::DnaMachine;
+
+/// The state of the machine at the start of an iteration.
+pub struct Checkpoint {
+    pub iteration: usize,
+    pub dna: DnaRope,
+    pub rna_count: usize
+}
+
+/// The sizes of the DNA and RNA at the start of an iteration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub dna_length: usize,
+    pub rna_count: usize
+}
+
+impl Record {
+    pub fn of(machine: &DnaMachine) -> Record {
+        Record { dna_length: machine.dna.len(), rna_count: machine.rna.len() }
+    }
+}
+
+pub struct History {
+    interval: usize,
+    checkpoints: Vec<Checkpoint>,
+    // records[i] is for iteration first + i
+    first: usize,
+    records: Vec<Record>
+}
+
+impl History {
+    /// Creates a history that keeps a checkpoint every `interval`
+    /// iterations.
+    pub fn new(interval: usize) -> History {
+        History { interval: interval.max(1), checkpoints: Vec::new(), first: 0, records: Vec::new() }
+    }
+
+    /// Records the state of `machine`, then runs a single iteration.  All
+    /// steps should go through here for the history to stay complete.
+    pub fn step(&mut self, machine: &mut DnaMachine) -> bool {
+        let iteration = machine.iteration;
+        let saved = self.checkpoints.binary_search_by_key(&iteration, |c| c.iteration);
+        if let Err(i) = saved {
+            if self.checkpoints.is_empty() || iteration.is_multiple_of(self.interval) {
+                let checkpoint = Checkpoint { iteration, dna: machine.dna.clone(), rna_count: machine.rna.len() };
+                self.checkpoints.insert(i, checkpoint);
+            }
+        }
+        if iteration < self.first || iteration > self.first + self.records.len() {
+            self.first = iteration;
+            self.records.clear();
+        }
+        self.records.truncate(iteration - self.first);
+        self.records.push(Record::of(machine));
+        machine.step()
+    }
+
+    /// The record of `iteration`, if it has run.
+    pub fn record(&self, iteration: usize) -> Option<Record> {
+        iteration.checked_sub(self.first).and_then(|i| self.records.get(i)).cloned()
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Moves `machine` to the start of `iteration`, going back to a
+    /// checkpoint if it is in the past, and running forward from there.
+    /// Returns false if there is no checkpoint early enough, or the machine
+    /// stopped before reaching `iteration`.
+    pub fn goto(&mut self, machine: &mut DnaMachine, iteration: usize) -> bool {
+        // later checkpoints are no use going forward, as the RNA after the
+        // machine's iteration has been discarded
+        if iteration < machine.iteration {
+            let checkpoint = match self.checkpoints.iter().rev().find(|c| c.iteration <= iteration) {
+                Some(c) => c,
+                None => return false
+            };
+            machine.restore(checkpoint.iteration, checkpoint.dna.clone(), checkpoint.rna_count);
+        }
+        while machine.iteration < iteration && self.step(machine) {}
+        machine.iteration == iteration
+    }
+}
+
+#[test]
+fn goto_test() {
+    // each iteration emits one RNA command and leaves the next instruction
+    let dna = "IIIPIPIIPCIIPIPCPIICIICIPPPIIC".repeat(30);
+    let mut machine = DnaMachine::new(DnaRope::from_bases(&dna).unwrap());
+    let mut history = History::new(4);
+    let mut states = Vec::new();
+    loop {
+        states.push((String::from(&machine.dna), machine.rna.len()));
+        if !history.step(&mut machine) {
+            break;
+        }
+    }
+    let end = machine.iteration;
+    assert_eq!(30, end);
+    assert_eq!(8, history.checkpoints().len());
+    assert_eq!(Some(Record { dna_length: states[13].0.len(), rna_count: 13 }), history.record(13));
+
+    for &i in [13, 2, 29, 0, 30, 8].iter() {
+        assert!(history.goto(&mut machine, i));
+        assert_eq!(i, machine.iteration);
+        assert_eq!(states[i], (String::from(&machine.dna), machine.rna.len()));
+    }
+    assert!(!history.goto(&mut machine, 31));
+    assert!(machine.is_finished());
+    assert_eq!(states[end], (String::from(&machine.dna), machine.rna.len()));
+}
@@ -0,0 +1,85 @@
+//! DNA and RNA processing for the ICFP 2007 Endo task.
+//!
+//! The pipeline has two phases: DNA is executed by [`dna::execute`] to
+//! produce a sequence of RNA commands, and the RNA is then rendered to an
+//! image by [`rna::build`].  [`Machine`] ties the two together for callers
+//! that just want to go from a prefix to a picture.
+
+extern crate xi_rope;
+extern crate image;
+
+pub mod dna;
+pub mod rna;
+pub mod gene;
+pub mod snapshot;
+pub mod prefix;
+pub mod disasm;
+pub mod asm;
+pub mod debug;
+pub mod history;
+pub mod calltrace;
+pub mod profile;
+pub mod score;
+pub mod apng;
+
+use std::io;
+
+pub use xi_rope::tree::{DnaRope, InvalidBase};
+
+/// The error returned by the readers and parsers of the text formats for
+/// malformed input.
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parses a decimal number, failing with an `invalid` error.
+fn number(s: &str) -> io::Result<usize> {
+    s.parse::<usize>().map_err(|_| invalid(format!("expected a number, found '{}'", s)))
+}
+
+/// A Fuun DNA processor, holding the DNA to execute and the options used
+/// while executing it.
+pub struct Machine {
+    pub dna: DnaRope,
+    pub logging: bool,
+    pub tracing: bool,
+    /// The genes that `tracing` reports calls to by name.
+    pub genes: Vec<gene::Gene>,
+    pub limits: dna::Limits
+}
+
+impl Machine {
+    /// Creates a machine that will execute `dna` as is.
+    pub fn new(dna: DnaRope) -> Machine {
+        Machine { dna, logging: false, tracing: false, genes: Vec::new(), limits: dna::Limits::default() }
+    }
+
+    /// Creates a machine that will execute `prefix` followed by `endo`, or
+    /// returns an error if `prefix` has anything but the bases `ICFP`.
+    /// Tracing names the genes of `endo`'s gene table.
+    pub fn with_prefix(prefix: &str, endo: DnaRope) -> Result<Machine, InvalidBase> {
+        let genes = gene::gene_table(&endo);
+        let mut dna = DnaRope::from_bases(prefix)?;
+        dna.push(endo);
+        let mut machine = Machine::new(dna);
+        machine.genes = genes;
+        Ok(machine)
+    }
+
+    /// Runs the DNA to completion, or until one of the `limits` is
+    /// reached, and returns the RNA it produced.
+    pub fn execute(self) -> Vec<rna::RnaCommand> {
+        dna::execute(self.dna, self.logging, self.tracing, &self.genes, self.limits)
+    }
+
+    /// Runs the DNA to completion and returns the rendered image, e.g. for
+    /// `score::score`.
+    pub fn image(self) -> rna::Bitmap {
+        rna::render(self.execute())
+    }
+
+    /// Runs the DNA to completion and renders the resulting RNA to `out_file`.
+    pub fn render(self, out_file: &str, render_intermediate: bool) {
+        rna::build(self.execute(), out_file, render_intermediate);
+    }
+}
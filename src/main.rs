@@ -1,123 +1,433 @@
-extern crate xi_rope;
-extern crate image;
+extern crate endo;
 extern crate getopts;
 
-mod dna;
-mod rna;
-mod gene;
-
+use std::io;
 use std::io::prelude::*;
+use std::io::BufWriter;
 use std::fs::File;
-use xi_rope::Rope;
+use std::fmt::Display;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+use endo::{dna, rna, gene, snapshot, prefix, disasm, asm, debug, score, DnaRope};
+use endo::dna::DnaMachine;
+use endo::history::History;
+use endo::calltrace::{self, CallTrace};
+use endo::profile::Profile;
 use getopts::Options;
 use std::env;
 
+fn fail<T: Display>(msg: T) -> ! {
+    eprintln!("endo: {}", msg);
+    process::exit(1)
+}
+
+fn read_dna(path: &str) -> DnaRope {
+    let mut s = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+        fail(format!("cannot read DNA file {}: {}", path, e));
+    }
+    to_dna(s.trim_end(), &format!("DNA file {}", path))
+}
+
+/// Converts bases given by the user to DNA, failing on anything but `ICFP`.
+fn to_dna(bases: &str, source: &str) -> DnaRope {
+    DnaRope::from_bases(bases).unwrap_or_else(|e| fail(format!("{} in {}", e, source)))
+}
+
+fn write_file<F>(path: &str, f: F) -> io::Result<()>
+        where F: FnOnce(&mut BufWriter<File>) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    f(&mut w)?;
+    w.flush()
+}
+
+/// Parses a number in decimal or `0x` hex.
+fn parse_number(s: &str) -> Option<usize> {
+    if s.len() > 2 && &s[0..2] == "0x" {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse::<usize>().ok()
+    }
+}
+
+/// Parses an `offset:length` green zone section.  Numbers that do not
+/// parse fall back to offset 0 and the length of the whole green zone.
+fn parse_section(s: &str) -> (usize, usize) {
+    let parts = s.split(':').collect::<Vec<&str>>();
+    let offset = parse_number(parts[0]).unwrap_or(0);
+    let length = parts.get(1).and_then(|s| parse_number(s)).unwrap_or(7509409);
+    (offset, length)
+}
+
+/// Parses an `offset:length` green zone section, failing on anything else.
+fn parse_section_strict(s: &str) -> (usize, usize) {
+    let parts = s.split(':').map(parse_number).collect::<Vec<_>>();
+    match parts[..] {
+        [Some(offset), Some(length)] => (offset, length),
+        _ => fail(format!("expected a green zone section OFFSET:LENGTH, found '{}'", s))
+    }
+}
+
+fn green_zone_slice(endo: &DnaRope, offset: usize, length: usize) -> DnaRope {
+    let start = gene::GREEN_ZONE_START.checked_add(offset);
+    match start.and_then(|start| start.checked_add(length).map(|end| (start, end))) {
+        Some((start, end)) if end <= endo.len() => endo.slice(start, end),
+        _ => fail(format!("green zone section {}:{} is outside the DNA", offset, length))
+    }
+}
+
+/// `endo disasm`: decodes DNA into a listing of patterns and templates
+/// without running it.
+fn disasm(args: &[String]) {
+    let mut opts = Options::new();
+    opts.optopt("d", "dna", "read Endo's DNA from a file", "endo.dna");
+    opts.optopt("f", "prefix-file", "disassemble a prefix read from a file", "FILE");
+    opts.optopt("z", "green-zone-section", "disassemble the green zone section at the provided offset and length", "0x000510:0x00018");
+    opts.optopt("", "gene", "disassemble the named gene from the gene table", "NAME");
+    opts.optopt("n", "count", "stop after this many instructions", "N");
+    let matches = match opts.parse(args) {
+        Ok(m) => { m }
+        Err(f) => fail(format!("{}\n{}", f, opts.usage("Usage: endo disasm [options] [PREFIX]")))
+    };
+    let dna_file = matches.opt_str("d").unwrap_or(String::from("endo.dna"));
+    let limit = matches.opt_str("n").map(|n| n.parse::<usize>().unwrap_or_else(|_| fail("--count expects a number of instructions")));
+    let prefix = matches.free.first().cloned();
+
+    let dna = if let Some(name) = matches.opt_str("gene") {
+        let endo = read_dna(&dna_file);
+        let gene = gene::gene_table(&endo).into_iter().find(|g| g.name == name)
+            .unwrap_or_else(|| fail(format!("no gene named {} in the gene table", name)));
+        green_zone_slice(&endo, gene.offset, gene.length)
+    } else if let Some(section) = matches.opt_str("z") {
+        let (offset, length) = parse_section_strict(&section);
+        green_zone_slice(&read_dna(&dna_file), offset, length)
+    } else if let Some(path) = matches.opt_str("f") {
+        to_dna(&prefix::load(&path).unwrap_or_else(|e| fail(format!("cannot read prefix file {}: {}", path, e))), &path)
+    } else if let Some(prefix) = prefix {
+        to_dna(&prefix, "prefix")
+    } else {
+        read_dna(&dna_file)
+    };
+
+    let listing = disasm::disassemble(&dna, limit);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    if let Err(e) = disasm::write(&listing, &dna, &mut out).and_then(|_| out.flush()) {
+        fail(format!("cannot write listing: {}", e));
+    }
+}
+
+/// `endo asm`: assembles pattern/template notation into DNA and prints it.
+fn asm(args: &[String]) {
+    let mut opts = Options::new();
+    opts.optopt("e", "expr", "assemble a single 'PATTERN -> TEMPLATE' instruction", "INSTRUCTION");
+    let matches = match opts.parse(args) {
+        Ok(m) => { m }
+        Err(f) => fail(format!("{}\n{}", f, opts.usage("Usage: endo asm [options] [FILE]")))
+    };
+    let dna = if let Some(expr) = matches.opt_str("e") {
+        asm::assemble(&expr).unwrap_or_else(|e| fail(e))
+    } else if let Some(path) = matches.free.first() {
+        asm::load(path).unwrap_or_else(|e| fail(format!("cannot assemble {}: {}", path, e)))
+    } else {
+        let mut text = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut text) {
+            fail(format!("cannot read standard input: {}", e));
+        }
+        asm::assemble(&text).unwrap_or_else(|e| fail(e))
+    };
+    println!("{}", dna);
+}
+
+/// `endo debug`: runs DNA under the interactive debugger.
+fn debug(args: &[String]) {
+    let mut opts = Options::new();
+    opts.optopt("d", "dna", "read Endo's DNA from a file", "endo.dna");
+    opts.optopt("f", "prefix-file", "read the prefix from a file ('#' starts a comment)", "FILE");
+    opts.optopt("", "resume", "debug from a saved snapshot instead of Endo", "FILE");
+    opts.optopt("", "checkpoint-every", "keep the DNA every N iterations for going back (default 1000)", "N");
+    let matches = match opts.parse(args) {
+        Ok(m) => { m }
+        Err(f) => fail(format!("{}\n{}", f, opts.usage("Usage: endo debug [options] [PREFIX]")))
+    };
+    let machine = if let Some(path) = matches.opt_str("resume") {
+        snapshot::load(&path).unwrap_or_else(|e| fail(format!("cannot read snapshot {}: {}", path, e)))
+    } else {
+        let mut dna = if let Some(path) = matches.opt_str("f") {
+            to_dna(&prefix::load(&path).unwrap_or_else(|e| fail(format!("cannot read prefix file {}: {}", path, e))), &path)
+        } else {
+            to_dna(matches.free.first().map_or("", |s| s.as_str()), "prefix")
+        };
+        dna.push(read_dna(&matches.opt_str("d").unwrap_or(String::from("endo.dna"))));
+        DnaMachine::new(dna)
+    };
+    let mut debugger = debug::Debugger::new(machine);
+    if let Some(n) = matches.opt_str("checkpoint-every") {
+        let n = n.parse::<usize>().unwrap_or_else(|_| fail("--checkpoint-every expects a number of iterations"));
+        debugger.history = History::new(n);
+    }
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(e) = debugger.run(stdin.lock(), stdout.lock()) {
+        fail(e);
+    }
+}
+
+/// `endo score`: renders a prefix and compares it with the target picture.
+fn score(args: &[String]) {
+    let mut opts = Options::new();
+    opts.optopt("d", "dna", "read Endo's DNA from a file", "endo.dna");
+    opts.optopt("f", "prefix-file", "read the prefix from a file ('#' starts a comment)", "FILE");
+    opts.optopt("", "rna-in", "score RNA read from a file instead of running the prefix", "FILE");
+    opts.optopt("t", "target", "the picture to compare with", "imgs/target.png");
+    opts.optopt("o", "diff", "write an image of the differing pixels", "diff.png");
+    let matches = match opts.parse(args) {
+        Ok(m) => { m }
+        Err(f) => fail(format!("{}\n{}", f, opts.usage("Usage: endo score [options] [PREFIX]")))
+    };
+    let prefix = if let Some(path) = matches.opt_str("f") {
+        prefix::load(&path).unwrap_or_else(|e| fail(format!("cannot read prefix file {}: {}", path, e)))
+    } else {
+        matches.free.first().cloned().unwrap_or_default()
+    };
+    let target_file = matches.opt_str("t").unwrap_or(String::from("imgs/target.png"));
+    let target = score::load_target(&target_file).unwrap_or_else(|e| fail(format!("cannot read target {}: {}", target_file, e)));
+    let rna = if let Some(path) = matches.opt_str("rna-in") {
+        rna::load(&path).unwrap_or_else(|e| fail(format!("cannot read RNA file {}: {}", path, e)))
+    } else {
+        let mut dna = to_dna(&prefix, "prefix");
+        dna.push(read_dna(&matches.opt_str("d").unwrap_or(String::from("endo.dna"))));
+        dna::execute(dna, false, false, &[], dna::Limits::default())
+    };
+    let image = rna::render(rna);
+    let s = score::score(&image, &target, prefix.len()).unwrap_or_else(|e| fail(format!("cannot score against {}: {}", target_file, e)));
+    println!("differing pixels = {}", s.differing);
+    println!("prefix length = {}", s.prefix_length);
+    println!("risk = {}", s.risk());
+    let diff_file = matches.opt_str("o").unwrap_or(String::from("diff.png"));
+    let diff = score::diff_image(&image, &target).unwrap_or_else(|e| fail(format!("cannot compare with {}: {}", target_file, e)));
+    diff.save(&diff_file).unwrap_or_else(|e| fail(format!("cannot write diff image {}: {}", diff_file, e)));
+}
+
+/// Renders `rna` to `out_file`, and to an animation if asked for.
+fn render(rna: Vec<rna::RnaCommand>, out_file: &str, render_intermediates: bool, animation: Option<(String, rna::FrameOptions)>) {
+    if let Some((path, options)) = animation {
+        let frames = rna::animate(rna.clone(), &path, options).unwrap_or_else(|e| fail(format!("cannot write animation {}: {}", path, e)));
+        println!("Wrote {} frames to {}", frames, path);
+    }
+    rna::build(rna, out_file, render_intermediates);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|s| s.as_str()) {
+        Some("disasm") => return disasm(&args[2..]),
+        Some("asm") => return asm(&args[2..]),
+        Some("debug") => return debug(&args[2..]),
+        Some("score") => return score(&args[2..]),
+        _ => {}
+    }
     let mut opts = Options::new();
     opts.optflag("l", "log-dna", "log DNA processing");    
     opts.optflag("t", "trace", "trace Fuun gene execution using RNA C*CC markers");    
+    opts.optopt("", "trace-json", "write one JSON object per DNA iteration to a file", "FILE");
+    opts.optopt("", "calls-json", "write the tree of gene calls to a file as JSON", "FILE");
+    opts.optopt("", "calls-folded", "write the gene calls to a file as folded stacks for flamegraphs", "FILE");
+    opts.optflag("", "profile", "print statistics and timings of the DNA phase");
+    opts.optopt("", "profile-csv", "write the DNA length and RNA count of each iteration to a CSV file", "FILE");
     opts.optflag("x", "gene-table", "render the gene table contents");    
     opts.optflag("i", "intermediate-rna", "render intermediate rna");
+    opts.optopt("", "animate", "also write the drawing of the picture to an animated PNG", "FILE");
+    opts.optopt("", "frame-every", "add an animation frame every N RNA commands (default 1000)", "N");
+    opts.optflag("", "frame-on-ops", "add an animation frame after each compose, clip and fill");
     opts.optopt("p", "page", "use prefix for rendering repair guide page #", "3");
     opts.optopt("g", "gene-table-page", "use prefix for rendering gene table page #", "3");
     opts.optopt("z", "green-zone-section", "print the green zone section at the provided offset and length", "0x000510:0x00018");
     opts.optopt("o", "out", "set output file name", "out.png");
+    opts.optopt("d", "dna", "read Endo's DNA from a file", "endo.dna");
+    opts.optopt("f", "prefix-file", "read the prefix from a file ('#' starts a comment)", "FILE");
+    opts.optopt("", "snapshot", "save the DNA machine state to a file", "FILE");
+    opts.optopt("", "snapshot-at", "iteration at which to save the snapshot (default: end of run)", "N");
+    opts.optopt("", "rna-out", "write the RNA produced by the DNA phase to a file", "FILE");
+    opts.optopt("", "rna-in", "render RNA read from a file, skipping the DNA phase", "FILE");
+    opts.optopt("", "resume", "resume execution from a saved snapshot instead of Endo", "FILE");
+    opts.optopt("", "max-iterations", "stop the DNA phase after N iterations in total", "N");
+    opts.optopt("", "max-dna", "stop the DNA phase if the DNA grows past N bases", "N");
+    opts.optopt("", "max-rna", "stop the DNA phase once N RNA commands have been emitted", "N");
+    opts.optopt("", "max-time", "stop the DNA phase after SECONDS of running", "SECONDS");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
-        Err(f) => { panic!(f.to_string()) }
+        Err(f) => fail(format!("{}\n{}", f, opts.usage("Usage: endo [options] [PREFIX]\n       endo disasm [options] [PREFIX]\n       endo asm [options] [FILE]\n       endo debug [options] [PREFIX]\n       endo score [options] [PREFIX]")))
     };
     
     let outpng = "out.png";
     
     let render_intermediates = matches.opt_present("i");
+    let animation = matches.opt_str("animate").map(|path| {
+        let on_compose_clip_fill = matches.opt_present("frame-on-ops");
+        let interval = match matches.opt_str("frame-every") {
+            Some(n) => Some(n.parse::<usize>().unwrap_or_else(|_| fail("--frame-every expects a number of RNA commands"))),
+            None if on_compose_clip_fill => None,
+            None => Some(1000)
+        };
+        (path, rna::FrameOptions { interval, on_compose_clip_fill })
+    });
     let log_dna = matches.opt_present("l");
     let tracing = matches.opt_present("t");
+    let trace_json = matches.opt_str("trace-json");
+    let calls_json = matches.opt_str("calls-json");
+    let calls_folded = matches.opt_str("calls-folded");
+    let follow_calls = tracing || calls_json.is_some() || calls_folded.is_some();
+    let profile_csv = matches.opt_str("profile-csv");
+    let profiling = matches.opt_present("profile") || profile_csv.is_some();
+    let snapshot_file = matches.opt_str("snapshot");
+    let snapshot_at = matches.opt_str("snapshot-at").map(|n| n.parse::<usize>().unwrap_or_else(|_| fail("--snapshot-at expects an iteration number")));
+    let resume_file = matches.opt_str("resume");
+    let limit = |name: &str| matches.opt_str(name).map(|n| n.parse::<usize>().unwrap_or_else(|_| fail(format!("--{} expects a number", name))));
+    let limits = dna::Limits {
+        iterations: limit("max-iterations"),
+        dna_length: limit("max-dna"),
+        rna: limit("max-rna"),
+        time: matches.opt_str("max-time").map(|t| match t.parse::<f64>() {
+            Ok(t) if t >= 0.0 && t.is_finite() => Duration::from_secs_f64(t),
+            _ => fail("--max-time expects a number of seconds")
+        })
+    };
+    let rna_out = matches.opt_str("rna-out");
+    let rna_in = matches.opt_str("rna-in");
     let show_gene_table = matches.opt_present("x");
+    let dna_file = matches.opt_str("d").unwrap_or(String::from("endo.dna"));
+    let prefix_file = matches.opt_str("f");
     
     let page = matches.opt_str("p").unwrap_or(String::new()).parse::<u32>();
     let gene_table_page = matches.opt_str("g").unwrap_or(String::new()).parse::<u32>();
-    let green_zone_section = matches.opt_str("z").map(|s| {
-       let parse_number = |s: &str, default: usize| {
-           if s.len() > 2 && &s[0..2] == "0x" {
-                usize::from_str_radix(&s[2..], 16).unwrap_or(default)
-           } else {
-               s.parse::<usize>().unwrap_or(default)
-           }
-       };
-       let parts = s.split(':').collect::<Vec<&str>>();
-       let offset = parse_number(parts[0], 0);
-       let length = parse_number(parts[1], 7509409);
-       (offset, length)
-    });
+    let green_zone_section = matches.opt_str("z").map(|s| parse_section(&s));
     let mut out_file = matches.opt_str("o").unwrap_or(String::from(outpng));
     let mut prefix = matches.free.into_iter().next().unwrap_or(String::new());
 
     // Update out_file and prefix based on other flags 
-    if prefix.len() > 0 && out_file == outpng {
+    if let Some(path) = prefix_file {
+        if !prefix.is_empty() {
+            fail("give either a prefix or --prefix-file, not both");
+        }
+        prefix = prefix::load(&path).unwrap_or_else(|e| fail(format!("cannot read prefix file {}: {}", path, e)));
+        if out_file == outpng {
+            let stem = Path::new(&path).file_stem().map_or(String::from("prefix"), |s| s.to_string_lossy().into_owned());
+            out_file = stem + ".png";
+        }
+    } else if !prefix.is_empty() && out_file == outpng {
         out_file = prefix.clone() + ".png";
     } else if let Ok(p) = page {
-        let mut num = String::from("CCCCCCCCCCCCCCCCCCCCCCC").into_bytes();
-        {
-            let mut p = p;
-            let mut i = 0;
-            while p > 0 {
-                if p % 2 == 1 {
-                    num[i] = b'F';
-                }
-                p = p / 2;
-                i += 1;
-            }
-        }
-        prefix = format!("IIPIFFCPICFPPICIICCCCCCCCCCCCCCCCCCCCCCCCIICIPPP{}IIC", String::from_utf8(num).unwrap());
+        prefix = prefix::repair_guide_page(p as usize);
         out_file = format!("page{}-{}.png", p, prefix);
     } else if let Ok(g) = gene_table_page {
-        let mut num = String::from("CCCCCCCCCCCCCCCCCCCCCCCIC").into_bytes();
-        {
-            let mut p = g - 1;
-            let mut i = 0;
-            while p > 0 {
-                if p % 2 == 1 {
-                    num[i] = b'F';
-                }
-                p = p / 2;
-                i += 1;
-            }
-        }
-        prefix = format!("IIPIFFCPICCFPICICFPPICICIPCCIIIIIICICPIICIIPIPIIICCPIICIICIPPP{}IICIIPIFFCPICFPPICIICCCCCCCCCCCCCCCCCCCCCCCCIICIPPPCFCFCFCCCCCCCCCCCCCCCCCIIC", String::from_utf8(num).unwrap());
+        prefix = prefix::gene_table_page(g as usize);
         out_file = format!("genetable{}-{}.png", g, prefix);
     }
     
-    // Get Endo
-    let mut f = File::open("endo.dna").unwrap();
-    let mut s = String::new();
-    let _ = f.read_to_string(&mut s); 
-    let endo = Rope::from(&s);
+    // Convert RNA -> Image(s) directly from a file, without running any DNA
+    if let Some(path) = rna_in {
+        let rna = rna::load(&path).unwrap_or_else(|e| fail(format!("cannot read RNA file {}: {}", path, e)));
+        println!("#RNA = {}", rna.len());
+        render(rna, &out_file, render_intermediates, animation);
+        return;
+    }
+
+    // Get Endo, which is not needed when resuming from a snapshot
+    let needs_endo = resume_file.is_none() || show_gene_table || green_zone_section.is_some() || follow_calls;
+    let endo = if needs_endo { read_dna(&dna_file) } else { DnaRope::default() };
 
     if show_gene_table {
         println!("*** Gene Table ***");
         for gene in gene::gene_table(&endo).iter() {
-            let bases = endo.clone().slice(13615 + gene.offset, 13615 + gene.offset + gene.length);
+            let start = gene::GREEN_ZONE_START + gene.offset;
+            if start + gene.length > endo.len() {
+                fail(format!("gene {} at {}:{} is outside the DNA of {} bases", gene.name, gene.offset, gene.length, endo.len()));
+            }
+            let bases = endo.slice(start, start + gene.length);
             println!("{:30} [{:8}:{:8}]: {}", gene.name, gene.offset, gene.length, dna::dna_to_string(&bases));
         }    
-        println!("");
+        println!();
     }
     
     if let Some((offset, length)) = green_zone_section {
         // Green Zone starts at 13616
-        let segment = endo.clone().slice(13615 + offset, 13615 + offset + length);
+        let segment = green_zone_slice(&endo, offset, length);
         println!("Green zone at offset {} of length {}:\n{}", offset, length, String::from(segment));
         return;
     }
     
-    // Prepare DNA from Endo and prefix
-    let mut dna = Rope::from(prefix);
-    dna.push(endo);
+    // Prepare DNA from a snapshot, or from Endo and prefix
+    let mut machine = match resume_file {
+        Some(path) => {
+            let machine = snapshot::load(&path).unwrap_or_else(|e| fail(format!("cannot read snapshot {}: {}", path, e)));
+            println!("Resuming from {} at iteration {}", path, machine.iteration);
+            machine
+        },
+        None => {
+            let mut dna = to_dna(&prefix, "prefix");
+            dna.push(endo.clone());
+            DnaMachine::new(dna)
+        }
+    };
+    machine.logging = log_dna;
+    machine.progress = true;
+    machine.tracing = tracing;
+    if follow_calls {
+        machine.calls = Some(CallTrace::new(&gene::gene_table(&endo)));
+    }
+    machine.limits = limits;
+    if profiling {
+        machine.profile = Some(Profile::new());
+    }
+    if let Some(path) = trace_json {
+        let file = File::create(&path).unwrap_or_else(|e| fail(format!("cannot write JSON trace {}: {}", path, e)));
+        machine.json_trace = Some(Box::new(BufWriter::new(file)));
+    }
     
     // Convert DNA -> RNA
-    let rna = dna::execute(dna, log_dna, tracing);
+    if let Some(path) = snapshot_file {
+        if let Some(n) = snapshot_at {
+            let remaining = n.saturating_sub(machine.iteration);
+            machine.run_for(remaining);
+        } else {
+            machine.run();
+        }
+        snapshot::save(&machine, &path).unwrap_or_else(|e| fail(format!("cannot write snapshot {}: {}", path, e)));
+        println!("Saved snapshot at iteration {} to {}", machine.iteration, path);
+    }
+    machine.run();
+    if let Some(limit) = machine.limit() {
+        eprintln!("endo: stopped at iteration {} with {} bases of DNA and {} RNA commands: {}",
+            machine.iteration, machine.dna.len(), machine.rna.len(), limit);
+    } else if let Some(e) = machine.error() {
+        eprintln!("endo: {}", e);
+    } else {
+        println!("DNA finished after {} iterations", machine.iteration);
+    }
+    if let Some(calls) = machine.calls.take() {
+        let calls = calls.into_calls(machine.iteration);
+        if let Some(path) = calls_json {
+            write_file(&path, |w| calltrace::write_json(&calls, w)).unwrap_or_else(|e| fail(format!("cannot write calls {}: {}", path, e)));
+        }
+        if let Some(path) = calls_folded {
+            write_file(&path, |w| calltrace::write_folded(&calls, w)).unwrap_or_else(|e| fail(format!("cannot write calls {}: {}", path, e)));
+        }
+    }
+    if let Some(profile) = machine.profile.take() {
+        println!();
+        profile.write_summary(&mut io::stdout()).unwrap_or_else(|e| fail(format!("cannot write profile: {}", e)));
+        if let Some(path) = profile_csv {
+            write_file(&path, |w| profile.write_csv(w)).unwrap_or_else(|e| fail(format!("cannot write profile {}: {}", path, e)));
+        }
+    }
+    let rna = machine.rna;
     println!("#RNA = {}", rna.len());
+    if let Some(path) = rna_out {
+        rna::save(&rna, &path).unwrap_or_else(|e| fail(format!("cannot write RNA file {}: {}", path, e)));
+    }
 
     // Convert RNA -> Image(s)
-    rna::build(rna, &out_file, render_intermediates);
+    render(rna, &out_file, render_intermediates, animation);
 }
\ No newline at end of file
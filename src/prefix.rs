@@ -0,0 +1,182 @@
+//! Building DNA prefixes.
+//!
+//! The encoding functions produce the bases that `dna::execute` decodes:
+//! `pattern` and `template` turn lists of `PItem` and `TItem` into DNA,
+//! so a prefix can be written as structured code rather than raw bases.
+//!
+//! A prefix file holds the bases of a prefix, optionally split across
+//! several lines.  Whitespace is ignored and `#` starts a comment that runs
+//! to the end of the line, so a prefix can be laid out and annotated:
+//!
+//! ```text
+//! # flip the bit at 14867
+//! IIP IFFCPICFPPIC IIC   # (?"IFPCFFP")
+//! C IIC                  # I
+//! IPPP F IIC             # \0C
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use dna::{PItem, TItem};
+use rna::RnaCommand;
+use xi_rope::tree::is_base;
+use invalid;
+
+/// Encodes a natural number: binary, least significant bit first, with `I`
+/// for 0 and `C` for 1, terminated by `P`.
+pub fn nat(mut n: usize) -> String {
+    let mut s = String::new();
+    while n > 0 {
+        s.push(if n.is_multiple_of(2) { 'I' } else { 'C' });
+        n /= 2;
+    }
+    s.push('P');
+    s
+}
+
+/// The `width` low bits of `n` as raw bases, least significant bit first,
+/// with `I` for 0 and `C` for 1.  This is how Endo stores fixed-width
+/// integers, and with a trailing `P` it is also a valid `nat`.
+pub fn bits(mut n: usize, width: usize) -> String {
+    let mut s = String::with_capacity(width);
+    for _ in 0..width {
+        s.push(if n.is_multiple_of(2) { 'I' } else { 'C' });
+        n /= 2;
+    }
+    s
+}
+
+/// Quotes `bases` so that they decode back to themselves as constants in a
+/// pattern or template.
+pub fn quote(bases: &str) -> String {
+    let mut s = String::with_capacity(bases.len());
+    for c in bases.chars() {
+        s.push_str(match c {
+            'I' => "C",
+            'C' => "F",
+            'F' => "P",
+            'P' => "IC",
+            _ => panic!("invalid base '{}'", c)
+        });
+    }
+    s
+}
+
+/// Encodes an RNA command so it is emitted when the DNA is decoded.
+pub fn rna(command: &RnaCommand) -> String {
+    format!("III{}", command)
+}
+
+/// Encodes a pattern, including the bases that terminate it.
+///
+/// The decoder reads a search constant for as long as it sees quoted
+/// bases, so a `Search` directly followed by a `Base` will not round-trip;
+/// put a `Close` or `Skip` in between.
+pub fn pattern(items: &[PItem]) -> String {
+    let mut s = String::new();
+    for item in items {
+        match *item {
+            PItem::Base(c) => s += &quote(&c.to_string()),
+            PItem::Skip(n) => s = s + "IP" + &nat(n),
+            PItem::Search(ref bases) => s = s + "IFF" + &quote(bases),
+            PItem::Open => s += "IIP",
+            PItem::Close => s += "IIC"
+        }
+    }
+    s + "IIC"
+}
+
+/// Encodes a template, including the bases that terminate it.
+pub fn template(items: &[TItem]) -> String {
+    let mut s = String::new();
+    for item in items {
+        match *item {
+            TItem::Base(c) => s += &quote(&c.to_string()),
+            TItem::Reference(n, l) => s = s + "IP" + &nat(l) + &nat(n),
+            TItem::Length(n) => s = s + "IIP" + &nat(n)
+        }
+    }
+    s + "IIC"
+}
+
+/// The prefix that shows page `page` of the Fuun Field Repair Guide.
+///
+/// It rewrites the 23-bit page number stored after the first `IFPCFFP` in
+/// Endo's DNA.
+pub fn repair_guide_page(page: usize) -> String {
+    let mut p = vec![PItem::Open, PItem::Search(String::from("IFPCFFP")), PItem::Close];
+    p.extend(bits(0, 23).chars().map(PItem::Base));
+    let mut t = vec![TItem::Reference(0, 0)];
+    t.extend(bits(page, 23).chars().map(TItem::Base));
+    pattern(&p) + &template(&t)
+}
+
+/// The prefix that shows page `page` (counting from 1) of the gene table.
+pub fn gene_table_page(page: usize) -> String {
+    let p = [
+        PItem::Open, PItem::Search(String::from("IFPICFPPCFFPP")), PItem::Skip(1283), PItem::Close,
+        PItem::Open, PItem::Skip(24), PItem::Close
+    ];
+    let mut t = vec![TItem::Reference(0, 0)];
+    t.extend((bits(page - 1, 23) + "P").chars().map(TItem::Base));
+    pattern(&p) + &template(&t) + &repair_guide_page(42)
+}
+
+/// The lines of `text` that are not blank once `#` comments are stripped,
+/// without their comments and numbered from 1.
+pub fn code_lines<'a>(text: &'a str) -> impl Iterator<Item = (usize, &'a str)> + 'a {
+    text.lines().enumerate().filter_map(|(n, line)| {
+        let code = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line
+        };
+        if code.trim().is_empty() { None } else { Some((n + 1, code)) }
+    })
+}
+
+/// Reads a prefix, stripping comments and whitespace.
+pub fn read<R: Read>(mut r: R) -> io::Result<String> {
+    let mut text = String::new();
+    r.read_to_string(&mut text)?;
+    let mut prefix = String::new();
+    for (n, code) in code_lines(&text) {
+        for c in code.chars().filter(|c| !c.is_whitespace()) {
+            if !is_base(c) {
+                return Err(invalid(format!("line {}: invalid base '{}' in prefix", n, c)));
+            }
+            prefix.push(c);
+        }
+    }
+    Ok(prefix)
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    read(File::open(path)?)
+}
+
+#[test]
+fn encode_test() {
+    assert_eq!("P", nat(0));
+    assert_eq!("IICP", nat(4));
+    assert_eq!("CICI", bits(5, 4));
+    assert_eq!("CFPIC", quote("ICFP"));
+    let p = [PItem::Open, PItem::Search(String::from("IFPP")), PItem::Close, PItem::Base('F')];
+    let t = [TItem::Reference(0, 0), TItem::Base('P')];
+    assert_eq!("IIPIFFCPICICIICPIICIPPPICIIC", pattern(&p) + &template(&t));
+    assert_eq!("IIIPIIIIIP", rna(&RnaCommand::Move));
+}
+
+#[test]
+fn page_prefix_test() {
+    assert_eq!("IIPIFFCPICFPPICIICCCCCCCCCCCCCCCCCCCCCCCCIICIPPPFFCCCCCCCCCCCCCCCCCCCCCIIC", repair_guide_page(3));
+    assert_eq!("IIPIFFCPICCFPICICFPPICICIPCCIIIIIICICPIICIIPIPIIICCPIICIICIPPPCFCCCCCCCCCCCCCCCCCCCCCICIICIIPIFFCPICFPPICIICCCCCCCCCCCCCCCCCCCCCCCCIICIPPPCFCFCFCCCCCCCCCCCCCCCCCIIC", gene_table_page(3));
+}
+
+#[test]
+fn read_test() {
+    let text = "# flip a bit\nIIP IFFCPICFPPIC IIC  # (?\"IFPCFFP\")\n\n  CIIC\nIPPPFIIC # \\0C\n";
+    assert_eq!("IIPIFFCPICFPPICIICCIICIPPPFIIC", read(text.as_bytes()).unwrap());
+    assert!(read("IIPX".as_bytes()).is_err());
+}
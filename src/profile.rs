@@ -0,0 +1,157 @@
+//! Counting what the DNA interpreter does, and where it spends its time.
+//!
+//! A `Profile` set on a `DnaMachine` is updated by every step: which
+//! pattern and template items ran, how many bases searches scanned, how
+//! much was quoted by references, how many matches failed, and the time
+//! taken to decode, match and replace.  It also samples the DNA length and
+//! RNA count at the start of each iteration, which can be written out as
+//! CSV for plotting.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::prelude::*;
+use std::time::Duration;
+use dna::{PItem, TItem};
+use history::Record;
+
+/// Number of pattern items of each kind that were executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PatternCounts {
+    pub base: usize,
+    pub skip: usize,
+    pub search: usize,
+    pub open: usize,
+    pub close: usize
+}
+
+impl PatternCounts {
+    pub fn count(&mut self, item: &PItem) {
+        match *item {
+            PItem::Base(_) => self.base += 1,
+            PItem::Skip(_) => self.skip += 1,
+            PItem::Search(_) => self.search += 1,
+            PItem::Open => self.open += 1,
+            PItem::Close => self.close += 1
+        }
+    }
+}
+
+/// Number of template items of each kind that were executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TemplateCounts {
+    pub base: usize,
+    pub reference: usize,
+    pub length: usize
+}
+
+impl TemplateCounts {
+    pub fn count(&mut self, item: &TItem) {
+        match *item {
+            TItem::Base(_) => self.base += 1,
+            TItem::Reference(..) => self.reference += 1,
+            TItem::Length(_) => self.length += 1
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// Iterations that decoded an instruction.
+    pub iterations: usize,
+    pub matches: usize,
+    pub failed_matches: usize,
+    pub pattern_items: PatternCounts,
+    pub template_items: TemplateCounts,
+    /// Bases looked at by `Search` items, up to the end of each match.
+    pub searched: usize,
+    /// Bases of captures inserted with a protection level above 0.
+    pub protected: usize,
+    /// Bases passed through `quote`, once for each level.
+    pub quoted: usize,
+    /// Number of references at each protection level.
+    pub levels: BTreeMap<usize, usize>,
+    pub decode_time: Duration,
+    pub match_time: Duration,
+    pub replace_time: Duration,
+    /// The record of each iteration, with its number.
+    pub samples: Vec<(usize, Record)>
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    pub fn sample(&mut self, iteration: usize, record: Record) {
+        self.samples.push((iteration, record));
+    }
+
+    /// Writes a table of the counts and times.
+    pub fn write_summary<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let percent = |n: usize, total: usize| if total == 0 { 0.0 } else { 100.0 * n as f64 / total as f64 };
+        let p = &self.pattern_items;
+        let t = &self.template_items;
+        writeln!(w, "{:24}{:>14}", "iterations", self.iterations)?;
+        writeln!(w, "{:24}{:>14}{:>8.1}%", "  matched", self.matches, percent(self.matches, self.iterations))?;
+        writeln!(w, "{:24}{:>14}{:>8.1}%", "  failed", self.failed_matches, percent(self.failed_matches, self.iterations))?;
+        writeln!(w, "pattern items")?;
+        for &(name, n) in [("base", p.base), ("skip", p.skip), ("search", p.search), ("open", p.open), ("close", p.close)].iter() {
+            writeln!(w, "  {:22}{:>14}", name, n)?;
+        }
+        writeln!(w, "{:24}{:>14}", "bases searched", self.searched)?;
+        writeln!(w, "template items")?;
+        for &(name, n) in [("base", t.base), ("reference", t.reference), ("length", t.length)].iter() {
+            writeln!(w, "  {:22}{:>14}", name, n)?;
+        }
+        writeln!(w, "reference levels")?;
+        for (level, n) in &self.levels {
+            writeln!(w, "  {:<22}{:>14}", level, n)?;
+        }
+        writeln!(w, "{:24}{:>14}", "bases protected", self.protected)?;
+        writeln!(w, "{:24}{:>14}", "bases quoted", self.quoted)?;
+        let total = self.decode_time + self.match_time + self.replace_time;
+        writeln!(w, "time")?;
+        for &(name, d) in [("decode", self.decode_time), ("match", self.match_time), ("replace", self.replace_time)].iter() {
+            let share = if total.as_secs_f64() > 0.0 { 100.0 * d.as_secs_f64() / total.as_secs_f64() } else { 0.0 };
+            writeln!(w, "  {:22}{:>13.3}s{:>8.1}%", name, d.as_secs_f64(), share)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the samples as CSV, with a header line.
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "iteration,dna_length,rna_count")?;
+        for &(iteration, ref r) in &self.samples {
+            writeln!(w, "{},{},{}", iteration, r.dna_length, r.rna_count)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn profile_test() {
+    use xi_rope::tree::DnaRope;
+    use dna::DnaMachine;
+    // (?"IC")(!1) -> \0\1(2)|1| on FFICP, then F -> C on I
+    let mut profile = Some(Profile::new());
+    for dna in ["IIPIFFCFIICIIPIPCPIICIICIPPPIPICPCPIIPCPIICFFICP", "PIICFIICI"].iter() {
+        let mut machine = DnaMachine::new(DnaRope::from_bases(dna).unwrap());
+        machine.profile = profile;
+        machine.run_for(1);
+        profile = machine.profile;
+    }
+    let profile = profile.unwrap();
+    assert_eq!((2, 1, 1), (profile.iterations, profile.matches, profile.failed_matches));
+    assert_eq!(PatternCounts { base: 1, skip: 1, search: 1, open: 2, close: 2 }, profile.pattern_items);
+    assert_eq!(TemplateCounts { base: 0, reference: 2, length: 1 }, profile.template_items);
+    // FFIC is searched, and the P skipped is quoted twice, to IC and then CF
+    assert_eq!((4, 1, 3), (profile.searched, profile.protected, profile.quoted));
+    assert_eq!(vec![(&0, &1), (&2, &1)], profile.levels.iter().collect::<Vec<_>>());
+
+    let mut csv = Vec::new();
+    profile.write_csv(&mut csv).unwrap();
+    assert_eq!("iteration,dna_length,rna_count\n0,48,0\n0,9,0\n", String::from_utf8(csv).unwrap());
+    let mut summary = Vec::new();
+    profile.write_summary(&mut summary).unwrap();
+    assert!(String::from_utf8(summary).unwrap().contains("  failed                             1    50.0%\n"));
+}
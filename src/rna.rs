@@ -1,34 +1,269 @@
 use std::cmp;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
 use image::{ImageBuffer, Rgba, Pixel};
 use std::path::Path;
+use xi_rope::tree::check_bases;
+use apng::Apng;
+use invalid;
 
 type Pos = (u32, u32);
-type RGB = (u8, u8, u8);
-type Transparency = u8;
+pub type Rgb = (u8, u8, u8);
+pub type Transparency = u8;
 pub type Pix = Rgba<u8>;
 pub type Bitmap = ImageBuffer<Pix, Vec<u8>>; 
 
-enum Color {
-    RGB(RGB),
+/// The colors RNA can add to the bucket: the corners of the RGB cube, and
+/// fully transparent or opaque.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Transparent,
+    Opaque
+}
+
+/// What a color adds to the bucket.
+enum Component {
+    Rgb(Rgb),
     A(Transparency)
 }
 
-type Bucket = Vec<Color>;
+impl Color {
+    fn component(self) -> Component {
+        match self {
+            Color::Black => Component::Rgb(BLACK),
+            Color::Red => Component::Rgb(RED),
+            Color::Green => Component::Rgb(GREEN),
+            Color::Yellow => Component::Rgb(YELLOW),
+            Color::Blue => Component::Rgb(BLUE),
+            Color::Magenta => Component::Rgb(MAGENTA),
+            Color::Cyan => Component::Rgb(CYAN),
+            Color::White => Component::Rgb(WHITE),
+            Color::Transparent => Component::A(TRANSPARENT),
+            Color::Opaque => Component::A(OPAQUE)
+        }
+    }
+}
+
+/// The colors added since the bucket was last cleared, kept as running
+/// sums so the current pixel does not have to look at each of them again.
+#[derive(Default)]
+struct Bucket {
+    rsum: u32,
+    gsum: u32,
+    bsum: u32,
+    asum: u32,
+    rgbcount: u32,
+    acount: u32,
+    // the current pixel, until the next color is added or the bucket cleared
+    pixel: Option<Pix>
+}
+
+impl Bucket {
+    fn push(&mut self, c: Color) {
+        match c.component() {
+            Component::Rgb((r,g,b)) => {
+                self.rgbcount += 1;
+                self.rsum += r as u32;
+                self.gsum += g as u32;
+                self.bsum += b as u32;
+            },
+            Component::A(a) => {
+                self.acount += 1;
+                self.asum += a as u32;
+            }
+        }
+        self.pixel = None;
+    }
+
+    fn clear(&mut self) {
+        *self = Bucket::default();
+    }
+
+    fn pixel(&mut self) -> Pix {
+        if let Some(pix) = self.pixel {
+            return pix;
+        }
+        let rc = self.rsum.checked_div(self.rgbcount).unwrap_or(0);
+        let gc = self.gsum.checked_div(self.rgbcount).unwrap_or(0);
+        let bc = self.bsum.checked_div(self.rgbcount).unwrap_or(0);
+        let ac = self.asum.checked_div(self.acount).unwrap_or(255);
+        let pix = Rgba::from_channels((rc * ac / 255) as u8, (gc * ac / 255) as u8, (bc * ac / 255) as u8, ac as u8);
+        self.pixel = Some(pix);
+        pix
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 enum Dir { N, E, S, W}
 
-const BLACK: RGB = (0,0,0);
-const RED: RGB = (255,0,0);
-const GREEN: RGB = (0,255,0);
-const YELLOW: RGB = (255,255,0);
-const BLUE: RGB = (0,0,255);
-const MAGENTA: RGB = (255,0,255);
-const CYAN: RGB = (0,255,255);
-const WHITE: RGB = (255,255,255);
+const BLACK: Rgb = (0,0,0);
+const RED: Rgb = (255,0,0);
+const GREEN: Rgb = (0,255,0);
+const YELLOW: Rgb = (255,255,0);
+const BLUE: Rgb = (0,0,255);
+const MAGENTA: Rgb = (255,0,255);
+const CYAN: Rgb = (0,255,255);
+const WHITE: Rgb = (255,255,255);
 const TRANSPARENT: Transparency = 0;
 const OPAQUE: Transparency = 255;
 
+/// A single RNA command, decoded from its 7-base form.
+///
+/// Anything that is not one of the drawing commands from the spec is kept
+/// as `Unknown`, with its bases, so trace markers and junk survive a round
+/// trip through parsing and printing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RnaCommand {
+    AddColor(Color),
+    ClearBucket,
+    Move,
+    TurnCcw,
+    TurnCw,
+    Mark,
+    Line,
+    Fill,
+    AddBitmap,
+    Compose,
+    Clip,
+    Unknown(String)
+}
+
+const COMMANDS: [(&str, RnaCommand); 20] = [
+    ("PIPIIIC", RnaCommand::AddColor(Color::Black)),
+    ("PIPIIIP", RnaCommand::AddColor(Color::Red)),
+    ("PIPIICC", RnaCommand::AddColor(Color::Green)),
+    ("PIPIICF", RnaCommand::AddColor(Color::Yellow)),
+    ("PIPIICP", RnaCommand::AddColor(Color::Blue)),
+    ("PIPIIFC", RnaCommand::AddColor(Color::Magenta)),
+    ("PIPIIFF", RnaCommand::AddColor(Color::Cyan)),
+    ("PIPIIPC", RnaCommand::AddColor(Color::White)),
+    ("PIPIIPF", RnaCommand::AddColor(Color::Transparent)),
+    ("PIPIIPP", RnaCommand::AddColor(Color::Opaque)),
+    ("PIIPICP", RnaCommand::ClearBucket),
+    ("PIIIIIP", RnaCommand::Move),
+    ("PCCCCCP", RnaCommand::TurnCcw),
+    ("PFFFFFP", RnaCommand::TurnCw),
+    ("PCCIFFP", RnaCommand::Mark),
+    ("PFFICCP", RnaCommand::Line),
+    ("PIIPIIP", RnaCommand::Fill),
+    ("PCCPFFP", RnaCommand::AddBitmap),
+    ("PFFPCCP", RnaCommand::Compose),
+    ("PFFICCF", RnaCommand::Clip),
+];
+
+impl From<&str> for RnaCommand {
+    fn from(bases: &str) -> RnaCommand {
+        for &(b, ref command) in COMMANDS.iter() {
+            if b == bases {
+                return command.clone();
+            }
+        }
+        RnaCommand::Unknown(bases.to_string())
+    }
+}
+
+impl fmt::Display for RnaCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let RnaCommand::Unknown(ref bases) = *self {
+            return f.write_str(bases);
+        }
+        // every command but `Unknown` is in the table, as colors are limited
+        // to the ones of the spec
+        let &(bases, _) = COMMANDS.iter().find(|&(_, c)| c == self).expect("command missing from COMMANDS");
+        f.write_str(bases)
+    }
+}
+
+/// Writes `rna` one 7-base command per line.
+pub fn write<W: Write>(rna: &[RnaCommand], w: &mut W) -> io::Result<()> {
+    for r in rna {
+        writeln!(w, "{}", r)?;
+    }
+    Ok(())
+}
+
+/// Parses a command written as its 7 bases.
+pub fn parse_command(bases: &str) -> io::Result<RnaCommand> {
+    if let Err(e) = check_bases(bases) {
+        return Err(invalid(format!("{} in RNA", e)));
+    }
+    if bases.len() != 7 {
+        return Err(invalid(format!("expected 7 bases of RNA, found {}", bases.len())));
+    }
+    Ok(RnaCommand::from(bases))
+}
+
+/// Reads commands written by `write`.  Blank lines are ignored.
+pub fn read<R: BufRead>(r: R) -> io::Result<Vec<RnaCommand>> {
+    let mut rna = Vec::new();
+    for (n, line) in r.lines().enumerate() {
+        let line = line?;
+        let bases = line.trim();
+        if bases.is_empty() {
+            continue;
+        }
+        rna.push(parse_command(bases).map_err(|e| invalid(format!("line {}: {}", n + 1, e)))?);
+    }
+    Ok(rna)
+}
+
+pub fn save<P: AsRef<Path>>(rna: &[RnaCommand], path: P) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write(rna, &mut w)?;
+    w.flush()
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<RnaCommand>> {
+    read(BufReader::new(File::open(path)?))
+}
+
+#[test]
+fn rna_file_test() {
+    let rna = vec![RnaCommand::Move, RnaCommand::from("CFPICFP"), RnaCommand::AddColor(Color::Cyan)];
+    let mut buf = Vec::new();
+    write(&rna, &mut buf).unwrap();
+    assert_eq!("PIIIIIP\nCFPICFP\nPIPIIFF\n", String::from_utf8(buf.clone()).unwrap());
+    assert_eq!(rna, read(&buf[..]).unwrap());
+    assert!(read(&b"PIIIIIP\nPIIXIIP\n"[..]).is_err());
+    for &bad in [&b"PIIIIIP\nICF\n"[..], &b"PIIIIIP\nPIIIIIPIC\n"[..]].iter() {
+        let e = read(bad).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+        assert!(e.to_string().starts_with("line 2: expected 7 bases"), "{}", e);
+    }
+}
+
+#[test]
+fn color_display_test() {
+    let colors = [Color::Black, Color::Red, Color::Green, Color::Yellow, Color::Blue,
+                  Color::Magenta, Color::Cyan, Color::White, Color::Transparent, Color::Opaque];
+    for &c in colors.iter() {
+        let command = RnaCommand::AddColor(c);
+        assert_eq!(command, RnaCommand::from(command.to_string().as_str()));
+    }
+}
+
+#[test]
+fn rna_command_test() {
+    for &(bases, ref command) in COMMANDS.iter() {
+        assert_eq!(*command, RnaCommand::from(bases));
+        assert_eq!(bases, command.to_string());
+    }
+    let marker = RnaCommand::from("CFPICFP");
+    assert_eq!(RnaCommand::Unknown(String::from("CFPICFP")), marker);
+    assert_eq!("CFPICFP", marker.to_string());
+}
+
 struct State {
     bucket: Bucket,
     position: Pos,
@@ -37,6 +272,24 @@ struct State {
     bitmaps: Vec<Bitmap> 
 }
 
+/// `c * a / 255`, rounding down as the spec does.
+fn scale(c: u8, a: u8) -> u8 {
+    ((c as u32 * a as u32) / 255) as u8
+}
+
+/// Drops the alpha of RGBA pixels.
+fn to_rgb(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks_exact(4).flat_map(|p| p[..3].iter().cloned()).collect()
+}
+
+/// Puts the RGBA pixel `p0` over `p1`.
+fn compose_pixel(p0: &[u8], p1: &mut [u8]) {
+    let a0 = p0[3];
+    for i in 0..4 {
+        p1[i] = p0[i] + scale(p1[i], 255 - a0);
+    }
+}
+
 fn transparent_bitmap() -> Bitmap {
     ImageBuffer::from_pixel(600, 600, Rgba::from_channels(0,0,0,0))
 }
@@ -44,7 +297,7 @@ fn transparent_bitmap() -> Bitmap {
 impl State {
     fn new() -> State {
         State {
-            bucket: Vec::new(),
+            bucket: Bucket::default(),
             position: (0, 0),
             mark: (0, 0),
             dir: Dir::E,
@@ -80,7 +333,7 @@ impl State {
     }
     
     fn get_pixel(&mut self, (x, y): Pos) -> Pix {
-        self.bitmaps[0].get_pixel(x, y).clone()
+        *self.bitmaps[0].get_pixel(x, y)
     }
     
     fn set_pixel(&mut self, (x, y): Pos) {
@@ -115,18 +368,47 @@ impl State {
         }
     }
         
-    fn fill(&mut self, p: Pos, initial: Pix) {
-        let mut to_fill = vec![p];
-        loop {
-            match to_fill.pop() {
-                None => return,
-                Some((x,y)) => if self.get_pixel((x, y)) == initial {
-                    self.set_pixel((x, y));
-                    if x > 0 { to_fill.push((x-1, y)) }
-                    if x < 599 { to_fill.push((x+1, y)) }
-                    if y > 0 { to_fill.push((x, y-1)) }
-                    if y < 599 { to_fill.push((x, y+ 1)) }
-                } 
+    /// Fills the region of `initial` pixels around `p` a span at a time,
+    /// working on the raw buffer.  The new pixel must differ from `initial`.
+    fn fill(&mut self, (x, y): Pos, initial: Pix) {
+        let new = self.current_pixel().data;
+        let initial = initial.data;
+        let buf: &mut [u8] = &mut self.bitmaps[0];
+        let is_initial = |buf: &[u8], x: usize, y: usize| {
+            let i = (y * 600 + x) * 4;
+            buf[i..i + 4] == initial
+        };
+        // seeds to look at; each is filled with the span it lies in
+        let mut seeds = vec![(x as usize, y as usize)];
+        while let Some((x, y)) = seeds.pop() {
+            if !is_initial(buf, x, y) {
+                continue;
+            }
+            let mut x0 = x;
+            while x0 > 0 && is_initial(buf, x0 - 1, y) {
+                x0 -= 1;
+            }
+            let mut x1 = x;
+            while x1 < 599 && is_initial(buf, x1 + 1, y) {
+                x1 += 1;
+            }
+            let row = y * 600 * 4;
+            for pixel in buf[row + x0 * 4..row + (x1 + 1) * 4].chunks_mut(4) {
+                pixel.copy_from_slice(&new);
+            }
+            // one seed for each run of initial pixels above and below
+            for &ny in [y.wrapping_sub(1), y + 1].iter() {
+                if ny >= 600 {
+                    continue;
+                }
+                let mut in_run = false;
+                for nx in x0..=x1 {
+                    let inside = is_initial(buf, nx, ny);
+                    if inside && !in_run {
+                        seeds.push((nx, ny));
+                    }
+                    in_run = inside;
+                }
             }
         }
     }
@@ -136,80 +418,80 @@ impl State {
         self.bitmaps.insert(0, bitmap)
     }
     
-    fn compose(&mut self) {
+    /// Combines each pixel of the top bitmap with the one below it, then
+    /// drops the top bitmap.  Works on the raw RGBA bytes, so the loop can
+    /// be vectorized.
+    fn blend<F>(&mut self, f: F) where F: Fn(&[u8], &mut [u8]) {
         if self.bitmaps.len() < 2 { return }
-        for x in 0..600 {
-            for y in 0..600 {
-                let (r0, g0, b0, a0) = self.bitmaps[0].get_pixel(x, y).channels4();
-                let (r1, g1, b1, a1) = self.bitmaps[1].get_pixel(x, y).channels4();
-                self.bitmaps[1].put_pixel(x, y, Rgba::from_channels(
-                    r0 + (((r1 as u32) * ((255 - a0) as u32))/255) as u8,
-                    g0 + (((g1 as u32) * ((255 - a0) as u32))/255) as u8,
-                    b0 + (((b1 as u32) * ((255 - a0) as u32))/255) as u8,
-                    a0 + (((a1 as u32) * ((255 - a0) as u32))/255) as u8
-                ))
+        {
+            let (top, rest) = self.bitmaps.split_at_mut(1);
+            let src: &[u8] = &top[0];
+            let dst: &mut [u8] = &mut rest[0];
+            for (p0, p1) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+                f(p0, p1);
             }
         }
         let _ = self.bitmaps.remove(0);
     }
+
+    fn compose(&mut self) {
+        self.blend(compose_pixel)
+    }
     
     fn clip(&mut self) {
-        if self.bitmaps.len() < 2 { return }
-        for x in 0..600 {
-            for y in 0..600 {
-                let (_, _, _, a0) = self.bitmaps[0].get_pixel(x, y).channels4();
-                let (r1, g1, b1, a1) = self.bitmaps[1].get_pixel(x, y).channels4();
-                self.bitmaps[1].put_pixel(x, y, Rgba::from_channels(
-                    (((r1 as u32) * (a0 as u32))/255) as u8,
-                    (((g1 as u32) * (a0 as u32))/255) as u8,
-                    (((b1 as u32) * (a0 as u32))/255) as u8,
-                    (((a1 as u32) * (a0 as u32))/255) as u8
-                ))
+        self.blend(|p0, p1| {
+            let a0 = p0[3];
+            for c in p1.iter_mut() {
+                *c = scale(*c, a0);
             }
-        }
-        let _ = self.bitmaps.remove(0);
+        })
     }
     
     fn add_color(&mut self, c: Color) {
         self.bucket.push(c)       
     }
     
-    fn current_pixel(&self) -> Pix {
-        let mut rsum = 0;
-        let mut gsum = 0;
-        let mut bsum = 0;
-        let mut asum = 0;
-        let mut rgbcount = 0;
-        let mut acount = 0;
-        for c in &self.bucket {
-            match c {
-                &Color::RGB((r,g,b)) => {
-                    rgbcount += 1;
-                    rsum += r as u32;
-                    gsum += g as u32;
-                    bsum += b as u32;
-                },
-                &Color::A(a) => {
-                    acount += 1;
-                    asum += a as u32;
-                }
+    fn current_pixel(&mut self) -> Pix {
+        self.bucket.pixel()
+    }
+
+    /// The picture so far, as RGB: the bitmaps composed as if by as many
+    /// `compose` commands as it takes, with the alpha dropped as in `build`.
+    fn flatten(&self) -> Vec<u8> {
+        let mut rgba = self.bitmaps[self.bitmaps.len() - 1].to_vec();
+        for bitmap in self.bitmaps.iter().rev().skip(1) {
+            for (p0, p1) in bitmap.chunks_exact(4).zip(rgba.chunks_exact_mut(4)) {
+                compose_pixel(p0, p1);
             }
         }
-        let rc = if rgbcount == 0 { 0 } else { rsum / rgbcount };
-        let gc = if rgbcount == 0 { 0 } else { gsum / rgbcount };
-        let bc = if rgbcount == 0 { 0 } else { bsum / rgbcount };
-        let ac = if acount == 0 { 255 } else { asum / acount };
-        
-        Rgba::from_channels((rc * ac / 255) as u8, (gc * ac / 255) as u8, (bc * ac / 255) as u8, ac as u8)
+        to_rgb(&rgba)
+    }
+
+    fn execute(&mut self, r: RnaCommand) {
+        match r {
+            RnaCommand::AddColor(c) => self.add_color(c),
+            RnaCommand::ClearBucket => self.bucket.clear(),
+            RnaCommand::Move => self.move_dir(),
+            RnaCommand::TurnCcw => self.turn_counterclockwise(),
+            RnaCommand::TurnCw => self.turn_clockwise(),
+            RnaCommand::Mark => self.mark = self.position,
+            RnaCommand::Line => self.line(),
+            RnaCommand::Fill => self.try_fill(),
+            RnaCommand::AddBitmap => self.add_bitmap(transparent_bitmap()),
+            RnaCommand::Compose => self.compose(),
+            RnaCommand::Clip => self.clip(),
+            // Not a drawing command (e.g. a trace marker), so nothing to render
+            RnaCommand::Unknown(_) => ()
+        }
     }
 }
 
 #[test]
 fn current_pixel_1() {
     let mut state = State::new();
-    state.add_color(Color::A(TRANSPARENT));
-    state.add_color(Color::A(OPAQUE));
-    state.add_color(Color::A(OPAQUE));
+    state.add_color(Color::Transparent);
+    state.add_color(Color::Opaque);
+    state.add_color(Color::Opaque);
     let pixel = state.current_pixel().channels4();
     assert_eq!((0, 0, 0, 170), pixel);
 }
@@ -217,9 +499,9 @@ fn current_pixel_1() {
 #[test]
 fn current_pixel_2() {
     let mut state = State::new();
-    state.add_color(Color::RGB(BLACK));
-    state.add_color(Color::RGB(YELLOW));
-    state.add_color(Color::RGB(CYAN));
+    state.add_color(Color::Black);
+    state.add_color(Color::Yellow);
+    state.add_color(Color::Cyan);
     let pixel = state.current_pixel().channels4();
     assert_eq!((85, 170, 85, 255), pixel);
 }
@@ -227,9 +509,9 @@ fn current_pixel_2() {
 #[test]
 fn current_pixel_3() {
     let mut state = State::new();
-    state.add_color(Color::RGB(YELLOW));
-    state.add_color(Color::A(TRANSPARENT));
-    state.add_color(Color::A(OPAQUE));
+    state.add_color(Color::Yellow);
+    state.add_color(Color::Transparent);
+    state.add_color(Color::Opaque);
     let pixel = state.current_pixel().channels4();
     assert_eq!((127, 127, 0, 127), pixel);
 }
@@ -237,48 +519,143 @@ fn current_pixel_3() {
 #[test]
 fn current_pixel_4() {
     let mut state = State::new();
-    for _ in 0..18 { state.add_color(Color::RGB(BLACK)) }
-    for _ in 0..7 { state.add_color(Color::RGB(RED)) }
-    for _ in 0..39 { state.add_color(Color::RGB(MAGENTA)) }
-    for _ in 0..10 { state.add_color(Color::RGB(WHITE)) }
-    for _ in 0..3 { state.add_color(Color::A(OPAQUE)) }
-    for _ in 0..1 { state.add_color(Color::A(TRANSPARENT)) }
+    for _ in 0..18 { state.add_color(Color::Black) }
+    for _ in 0..7 { state.add_color(Color::Red) }
+    for _ in 0..39 { state.add_color(Color::Magenta) }
+    for _ in 0..10 { state.add_color(Color::White) }
+    for _ in 0..3 { state.add_color(Color::Opaque) }
+    for _ in 0..1 { state.add_color(Color::Transparent) }
     let pixel = state.current_pixel().channels4();
     assert_eq!((143, 25, 125, 191), pixel);
 }
 
-pub fn build(rna: Vec<String>, out_file: &str, render_intermediate: bool) {
+#[test]
+fn current_pixel_cached() {
+    let mut state = State::new();
+    assert_eq!((0, 0, 0, 255), state.current_pixel().channels4());
+    state.add_color(Color::Yellow);
+    assert_eq!((255, 255, 0, 255), state.current_pixel().channels4());
+    state.add_color(Color::Transparent);
+    state.add_color(Color::Opaque);
+    assert_eq!((127, 127, 0, 127), state.current_pixel().channels4());
+    state.bucket.clear();
+    assert_eq!((0, 0, 0, 255), state.current_pixel().channels4());
+}
+
+#[test]
+fn compose_clip_test() {
+    let bitmap = |p: Pix| ImageBuffer::from_pixel(600, 600, p);
+    let mut state = State::new();
+    state.bitmaps = vec![bitmap(Rgba::from_channels(0, 0, 127, 127)), bitmap(Rgba::from_channels(255, 0, 0, 255))];
+    state.compose();
+    assert_eq!(1, state.bitmaps.len());
+    assert_eq!((128, 0, 127, 255), state.bitmaps[0].get_pixel(599, 0).channels4());
+
+    state.bitmaps.insert(0, bitmap(Rgba::from_channels(10, 20, 30, 127)));
+    state.bitmaps[1].put_pixel(3, 4, Rgba::from_channels(255, 255, 0, 255));
+    state.clip();
+    assert_eq!((127, 127, 0, 127), state.bitmaps[0].get_pixel(3, 4).channels4());
+    assert_eq!((63, 0, 63, 127), state.bitmaps[0].get_pixel(0, 0).channels4());
+    state.compose();
+    assert_eq!(1, state.bitmaps.len());
+}
+
+#[test]
+fn frames_test() {
+    let mut state = State::new();
+    state.add_color(Color::Red);
+    state.add_color(Color::Opaque);
+    state.add_bitmap(transparent_bitmap());
+    state.line();
+    state.bitmaps[1].put_pixel(5, 5, Rgba::from_channels(0, 0, 255, 255));
+    let flat = state.flatten();
+    assert_eq!(&[255, 0, 0], &flat[..3]);
+    assert_eq!(&[0, 0, 255], &flat[(5 * 600 + 5) * 3..][..3]);
+    state.compose();
+    assert_eq!(flat, state.flatten());
+
+    // frames after the line and the fill; the moves change nothing, and the
+    // finished picture is the same as after the fill
+    let rna = vec![RnaCommand::AddColor(Color::Red), RnaCommand::Line, RnaCommand::Move, RnaCommand::Move,
+                   RnaCommand::AddColor(Color::Blue), RnaCommand::Fill, RnaCommand::Move];
+    let every_two = FrameOptions { interval: Some(2), on_compose_clip_fill: false };
+    assert_eq!(2, frames(rna.clone(), every_two).unwrap().len());
+    let on_fill = FrameOptions { interval: None, on_compose_clip_fill: true };
+    assert_eq!(1, frames(rna.clone(), on_fill).unwrap().len());
+    assert_eq!(1, frames(Vec::new(), FrameOptions::default()).unwrap().len());
+}
+
+/// The fill from before spans, one pixel at a time, to check `fill` against.
+#[cfg(test)]
+fn fill_by_pixel(bitmap: &mut Bitmap, p: Pos, initial: Pix, new: Pix) {
+    let mut to_fill = vec![p];
+    while let Some((x, y)) = to_fill.pop() {
+        if *bitmap.get_pixel(x, y) == initial {
+            bitmap.put_pixel(x, y, new);
+            if x > 0 { to_fill.push((x-1, y)) }
+            if x < 599 { to_fill.push((x+1, y)) }
+            if y > 0 { to_fill.push((x, y-1)) }
+            if y < 599 { to_fill.push((x, y+ 1)) }
+        }
+    }
+}
+
+/// Random drawing commands, weighted towards moves so lines get long.
+#[cfg(test)]
+fn random_rna(seed: u64, n: usize) -> Vec<RnaCommand> {
+    let mut x = seed;
+    (0..n).map(|_| {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let r = (x >> 33) as usize % 100;
+        match r {
+            0..=59 => RnaCommand::Move,
+            60..=69 => COMMANDS[r % 10].1.clone(),
+            70..=74 => RnaCommand::TurnCw,
+            75..=79 => RnaCommand::TurnCcw,
+            80..=84 => RnaCommand::Mark,
+            85..=93 => RnaCommand::Line,
+            94..=98 => RnaCommand::ClearBucket,
+            _ => RnaCommand::Fill
+        }
+    }).collect()
+}
+
+#[test]
+fn fill_test() {
+    for seed in 0..4 {
+        let mut state = State::new();
+        for r in random_rna(seed, 3000) {
+            if r == RnaCommand::Fill {
+                let mut expected = state.bitmaps[0].clone();
+                let pos = state.position;
+                let (initial, new) = (*expected.get_pixel(pos.0, pos.1), state.current_pixel());
+                if initial != new {
+                    fill_by_pixel(&mut expected, pos, initial, new);
+                }
+                state.try_fill();
+                assert!(*expected == *state.bitmaps[0], "fill differs with seed {}", seed);
+            } else {
+                state.execute(r);
+            }
+        }
+    }
+}
+
+/// Draws `rna`, saving the bitmap being drawn on every 100 commands as
+/// `<n>-<intermediate_file>` if given, and returns the finished, opaque
+/// image.
+fn draw(rna: Vec<RnaCommand>, intermediate_file: Option<&str>) -> Bitmap {
     let mut state = State::new();
     let mut iter = 0;
     for r in rna {
         iter += 1;
-        if iter % 100 == 0 && render_intermediate {
-            let s = format!("{}-{}", iter, out_file);
-            state.bitmaps[0].save(&Path::new(&s)).unwrap();
-        }
-        match r.as_ref() {
-            "PIPIIIC" => state.add_color(Color::RGB(BLACK)),
-            "PIPIIIP" => state.add_color(Color::RGB(RED)),
-            "PIPIICC" => state.add_color(Color::RGB(GREEN)),
-            "PIPIICF" => state.add_color(Color::RGB(YELLOW)),
-            "PIPIICP" => state.add_color(Color::RGB(BLUE)),
-            "PIPIIFC" => state.add_color(Color::RGB(MAGENTA)),
-            "PIPIIFF" => state.add_color(Color::RGB(CYAN)),
-            "PIPIIPC" => state.add_color(Color::RGB(WHITE)),
-            "PIPIIPF" => state.add_color(Color::A(TRANSPARENT)),
-            "PIPIIPP" => state.add_color(Color::A(OPAQUE)),
-            "PIIPICP" => state.bucket.clear(),
-            "PIIIIIP" => state.move_dir(),
-            "PCCCCCP" => state.turn_counterclockwise(),
-            "PFFFFFP" => state.turn_clockwise(),
-            "PCCIFFP" => state.mark = state.position,
-            "PFFICCP" => state.line(),
-            "PIIPIIP" => state.try_fill(),
-            "PCCPFFP" => state.add_bitmap(transparent_bitmap()),
-            "PFFPCCP" => state.compose(),
-            "PFFICCF" => state.clip(),
-            _ => ()
+        if let Some(out_file) = intermediate_file {
+            if iter % 100 == 0 {
+                let s = format!("{}-{}", iter, out_file);
+                state.bitmaps[0].save(Path::new(&s)).unwrap();
+            }
         }
+        state.execute(r);
     }
     let mut ret = state.bitmaps.remove(0);
     for x in 0..600 {
@@ -287,5 +664,67 @@ pub fn build(rna: Vec<String>, out_file: &str, render_intermediate: bool) {
             ret.put_pixel(x, y, p);
         }
     }
-    let _ = ret.save(&Path::new(&out_file)).unwrap();
-}
\ No newline at end of file
+    ret
+}
+
+/// Renders `rna` to an image, without saving it.
+pub fn render(rna: Vec<RnaCommand>) -> Bitmap {
+    draw(rna, None)
+}
+
+/// When `animate` adds a frame, besides the last one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// Add a frame every this many RNA commands.
+    pub interval: Option<usize>,
+    /// Add a frame after each compose, clip and fill.
+    pub on_compose_clip_fill: bool
+}
+
+/// Frames are shown for 40ms, and the finished picture for 2s.
+const FRAME_DELAY: u16 = 40;
+const LAST_FRAME_DELAY: u16 = 2000;
+
+/// Draws `rna`, taking frames of the whole picture so far as set by
+/// `options`, and a last one of the finished picture.  Frames that would
+/// repeat the previous one are left out.
+fn frames(rna: Vec<RnaCommand>, options: FrameOptions) -> io::Result<Apng> {
+    let mut state = State::new();
+    let mut apng = Apng::new(600, 600);
+    let mut last: Option<Vec<u8>> = None;
+    for (i, r) in rna.into_iter().enumerate() {
+        let blend = matches!(r, RnaCommand::Compose | RnaCommand::Clip | RnaCommand::Fill);
+        state.execute(r);
+        let on_interval = options.interval.is_some_and(|n| n > 0 && (i + 1) % n == 0);
+        if on_interval || (options.on_compose_clip_fill && blend) {
+            let frame = state.flatten();
+            if last.as_ref() != Some(&frame) {
+                apng.add_frame(&frame, FRAME_DELAY)?;
+                last = Some(frame);
+            }
+        }
+    }
+    // the finished picture is only the top bitmap, as in `build`
+    let frame = to_rgb(&state.bitmaps[0]);
+    if last.as_ref() != Some(&frame) {
+        apng.add_frame(&frame, FRAME_DELAY)?;
+    }
+    apng.set_last_delay(LAST_FRAME_DELAY);
+    Ok(apng)
+}
+
+/// Draws `rna` into an animated PNG at `out_file`, with frames as set by
+/// `options`.  Returns the number of frames.
+pub fn animate(rna: Vec<RnaCommand>, out_file: &str, options: FrameOptions) -> io::Result<usize> {
+    let apng = frames(rna, options)?;
+    let mut w = BufWriter::new(File::create(out_file)?);
+    apng.write(&mut w)?;
+    w.flush()?;
+    Ok(apng.len())
+}
+
+pub fn build(rna: Vec<RnaCommand>, out_file: &str, render_intermediate: bool) {
+    let intermediate_file = if render_intermediate { Some(out_file) } else { None };
+    let ret = draw(rna, intermediate_file);
+    ret.save(Path::new(&out_file)).unwrap();
+}
@@ -0,0 +1,99 @@
+//! Scoring a rendered image against the target picture.
+//!
+//! The contest ranked prefixes by their risk: ten for every pixel of the
+//! rendered image that differs from the target, plus one for every base of
+//! the prefix.  Lower is better, and a perfect prefix still pays for its
+//! length.
+
+use std::io;
+use std::path::Path;
+use image::{self, ImageBuffer, Rgba, Pixel};
+use rna::Bitmap;
+use invalid;
+
+/// How far a prefix is from producing the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Score {
+    pub differing: usize,
+    pub prefix_length: usize
+}
+
+impl Score {
+    /// The contest risk, `10 * differing + prefix_length`.
+    pub fn risk(&self) -> usize {
+        10 * self.differing + self.prefix_length
+    }
+}
+
+/// Reads the target picture, which must be 600x600 like the renders.
+pub fn load_target<P: AsRef<Path>>(path: P) -> io::Result<Bitmap> {
+    let target = image::open(path).map_err(|e| invalid(e.to_string()))?.to_rgba();
+    if target.dimensions() != (600, 600) {
+        let (w, h) = target.dimensions();
+        return Err(invalid(format!("target is {}x{}, not 600x600", w, h)));
+    }
+    Ok(target)
+}
+
+fn same(a: &Rgba<u8>, b: &Rgba<u8>) -> bool {
+    a.to_rgb() == b.to_rgb()
+}
+
+fn check_dimensions(image: &Bitmap, target: &Bitmap) -> io::Result<()> {
+    if image.dimensions() != target.dimensions() {
+        let ((w, h), (tw, th)) = (image.dimensions(), target.dimensions());
+        return Err(invalid(format!("image is {}x{} but the target is {}x{}", w, h, tw, th)));
+    }
+    Ok(())
+}
+
+/// Scores `image`, rendered from a prefix of `prefix_length` bases, against
+/// `target`, which must be the same size.  Only the colors are compared, as
+/// renders are opaque.
+pub fn score(image: &Bitmap, target: &Bitmap, prefix_length: usize) -> io::Result<Score> {
+    check_dimensions(image, target)?;
+    let differing = image.pixels().zip(target.pixels()).filter(|&(a, b)| !same(a, b)).count();
+    Ok(Score { differing, prefix_length })
+}
+
+/// An image of where `image` differs from `target`, which must be the same
+/// size: differing pixels are red, and the rest show the target at a
+/// quarter of its brightness.
+pub fn diff_image(image: &Bitmap, target: &Bitmap) -> io::Result<Bitmap> {
+    check_dimensions(image, target)?;
+    let (width, height) = target.dimensions();
+    Ok(ImageBuffer::from_fn(width, height, |x, y| {
+        let (a, b) = (image.get_pixel(x, y), target.get_pixel(x, y));
+        if same(a, b) {
+            let (r, g, b, _) = b.channels4();
+            Rgba::from_channels(r / 4, g / 4, b / 4, 255)
+        } else {
+            Rgba::from_channels(255, 0, 0, 255)
+        }
+    }))
+}
+
+#[test]
+fn score_test() {
+    let mut target = ImageBuffer::from_pixel(600, 600, Rgba::from_channels(200, 100, 40, 255));
+    target.put_pixel(5, 5, Rgba::from_channels(0, 0, 0, 255));
+    let mut image = target.clone();
+    assert_eq!(Score { differing: 0, prefix_length: 28 }, score(&image, &target, 28).unwrap());
+    image.put_pixel(5, 5, Rgba::from_channels(0, 0, 1, 255));
+    image.put_pixel(599, 599, Rgba::from_channels(0, 0, 0, 255));
+    // alpha is not compared
+    image.put_pixel(0, 0, Rgba::from_channels(200, 100, 40, 0));
+    let s = score(&image, &target, 28).unwrap();
+    assert_eq!(2, s.differing);
+    assert_eq!(48, s.risk());
+
+    let diff = diff_image(&image, &target).unwrap();
+    assert_eq!((255, 0, 0, 255), diff.get_pixel(599, 599).channels4());
+    assert_eq!((50, 25, 10, 255), diff.get_pixel(1, 0).channels4());
+
+    let small = ImageBuffer::from_pixel(600, 300, Rgba::from_channels(200, 100, 40, 255));
+    let e = score(&small, &target, 28).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, e.kind());
+    assert_eq!("image is 600x300 but the target is 600x600", e.to_string());
+    assert!(diff_image(&target, &small).is_err());
+}
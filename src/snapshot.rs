@@ -0,0 +1,134 @@
+//! Saving and restoring the state of a `DnaMachine`.
+//!
+//! A snapshot is a plain text file:
+//!
+//! ```text
+//! endo-snapshot 1
+//! iteration <n>
+//! rna <count>
+//! <one 7-base RNA command per line>
+//! dna <length>
+//! <the DNA bases>
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use xi_rope::tree::DnaRope;
+use dna::DnaMachine;
+use rna;
+use invalid;
+
+const HEADER: &str = "endo-snapshot 1";
+
+pub fn write<W: Write>(machine: &DnaMachine, w: &mut W) -> io::Result<()> {
+    writeln!(w, "{}", HEADER)?;
+    writeln!(w, "iteration {}", machine.iteration)?;
+    writeln!(w, "rna {}", machine.rna.len())?;
+    for r in &machine.rna {
+        writeln!(w, "{}", r)?;
+    }
+    writeln!(w, "dna {}", machine.dna.len())?;
+    w.write_all(String::from(&machine.dna).as_bytes())?;
+    writeln!(w)
+}
+
+fn read_line<R: BufRead>(r: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if r.read_line(&mut line)? == 0 {
+        return Err(invalid(String::from("unexpected end of snapshot")));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn read_field<R: BufRead>(r: &mut R, name: &str) -> io::Result<usize> {
+    let line = read_line(r)?;
+    let mut parts = line.splitn(2, ' ');
+    match (parts.next(), parts.next().map(|n| n.parse::<usize>())) {
+        (Some(key), Some(Ok(n))) if key == name => Ok(n),
+        _ => Err(invalid(format!("expected '{} <number>', found '{}'", name, line)))
+    }
+}
+
+pub fn read<R: BufRead>(r: &mut R) -> io::Result<DnaMachine> {
+    let header = read_line(r)?;
+    if header != HEADER {
+        return Err(invalid(format!("not a snapshot file (header '{}')", header)));
+    }
+    let iteration = read_field(r, "iteration")?;
+    // the counts are not trusted for preallocating, only checked against
+    // what is actually there
+    let rna_count = read_field(r, "rna")?;
+    let mut rna = Vec::new();
+    for i in 0..rna_count {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 || line.starts_with("dna ") {
+            return Err(invalid(format!("expected {} RNA commands, found {}", rna_count, i)));
+        }
+        let command = rna::parse_command(line.trim_end())
+            .map_err(|e| invalid(format!("RNA command {}: {}", i + 1, e)))?;
+        rna.push(command);
+    }
+    let dna_len = read_field(r, "dna")?;
+    let mut dna = String::new();
+    r.read_to_string(&mut dna)?;
+    let dna = dna.trim_end();
+    if dna.len() != dna_len {
+        return Err(invalid(format!("expected {} bases of DNA, found {}", dna_len, dna.len())));
+    }
+    let dna = DnaRope::from_bases(dna).map_err(|e| invalid(format!("{} in DNA", e)))?;
+    let mut machine = DnaMachine::new(dna);
+    machine.rna = rna;
+    machine.iteration = iteration;
+    Ok(machine)
+}
+
+/// Writes the state of `machine` to the file at `path`.
+pub fn save<P: AsRef<Path>>(machine: &DnaMachine, path: P) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write(machine, &mut w)?;
+    w.flush()
+}
+
+/// Reads a machine previously written by `save`, ready to resume execution.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<DnaMachine> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+#[test]
+fn round_trip_test() {
+    let mut machine = DnaMachine::new(DnaRope::from_bases("IIIPIPIIPCIICIICIIIPIPIIPFIICIIC").unwrap());
+    assert_eq!(1, machine.run_for(1));
+    let mut buf = Vec::new();
+    write(&machine, &mut buf).unwrap();
+    let mut resumed = read(&mut &buf[..]).unwrap();
+    assert_eq!(machine.iteration, resumed.iteration);
+    assert_eq!(machine.rna, resumed.rna);
+    assert!(machine.dna == resumed.dna);
+    resumed.run();
+    assert_eq!(vec![rna::RnaCommand::from("PIPIIPC"), rna::RnaCommand::from("PIPIIPF")], resumed.rna);
+}
+
+#[test]
+fn read_invalid_test() {
+    assert!(read(&mut &b"endo-snapshot 1\niteration 0\nrna 0\ndna 3\nIC\n"[..]).is_err());
+    assert!(read(&mut &b"endo-snapshot 1\niteration 0\nrna 0\ndna 2\nIX\n"[..]).is_err());
+    assert!(read(&mut &b"something else\n"[..]).is_err());
+    let e = read(&mut &b"endo-snapshot 1\niteration 0\nrna 2\nPIIIIIP\nXYZ\ndna 2\nIC\n"[..]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, e.kind());
+    assert_eq!("RNA command 2: invalid base 'X' at offset 0 in RNA", e.to_string());
+    assert!(read(&mut &b"endo-snapshot 1\niteration 0\nrna 1\nPIIIIP\ndna 2\nIC\n"[..]).is_err());
+}
+
+#[test]
+fn read_bogus_counts_test() {
+    let e = read(&mut &b"endo-snapshot 1\niteration 0\nrna 99999999999999999\nPIIIIIP\ndna 2\nIC\n"[..]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, e.kind());
+    assert_eq!("expected 99999999999999999 RNA commands, found 1", e.to_string());
+    let e = read(&mut &b"endo-snapshot 1\niteration 0\nrna 0\ndna 99999999999999999\nIC\n"[..]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, e.kind());
+    let e = read(&mut &b"endo-snapshot 1\niteration 0\nrna 0\ndna 99999999999999999999999\nIC\n"[..]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, e.kind());
+}